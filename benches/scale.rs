@@ -0,0 +1,73 @@
+#![allow(clippy::needless_return)]
+
+// Times `history-tidy list` over generated histories of growing length. Building the hashtag
+// map is linear, so 4 times the lines should take about 4 times as long, not 16.
+use std::env::temp_dir;
+use std::fs::create_dir_all;
+use std::fs::remove_dir_all;
+use std::fs::write;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Output;
+use std::time::Duration;
+use std::time::Instant;
+
+// the same commands as the tests of parse_history.rs, each with two of 50 hashtags
+fn generate_history(len: usize, seed: u64) -> String {
+    let mut state: u64 = seed;
+    let mut next = |n: u64| -> u64 {
+        state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        return (state >> 33) % n;
+    };
+    return (0..len)
+        .map(|_| {
+            format!(
+                "cmd{} #tag{} #tag{}\n",
+                next(len as u64 / 2 + 1),
+                next(50),
+                next(50)
+            )
+        })
+        .collect();
+}
+
+fn measure(len: usize) -> Duration {
+    let home_path: PathBuf =
+        temp_dir().join(format!("history-tidy-bench-{}-{}", std::process::id(), len));
+    let tidy_dir_path: PathBuf = home_path.join(".history-tidy");
+    create_dir_all(&tidy_dir_path).unwrap();
+    write(tidy_dir_path.join("history"), generate_history(len, 2)).unwrap();
+
+    // a new home has no index yet, so the whole history is read
+    let start: Instant = Instant::now();
+    let output: Output = Command::new(env!("CARGO_BIN_EXE_history-tidy"))
+        .arg("list")
+        .env("HOME", &home_path)
+        .env_remove("HISTFILE")
+        .output()
+        .unwrap();
+    let elapsed: Duration = start.elapsed();
+    assert!(output.status.success());
+
+    remove_dir_all(&home_path).unwrap();
+    return elapsed;
+}
+
+fn main() {
+    let mut last: Option<Duration> = None;
+    for len in [25_000, 100_000, 400_000] {
+        let elapsed: Duration = measure(len);
+        match last {
+            Some(last) => println!(
+                "{:>7} lines: {:?} ({:.1}x)",
+                len,
+                elapsed,
+                elapsed.as_secs_f64() / last.as_secs_f64()
+            ),
+            None => println!("{:>7} lines: {:?}", len, elapsed),
+        }
+        last = Some(elapsed);
+    }
+}
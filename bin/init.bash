@@ -1,14 +1,62 @@
 #!/bin/bash
+# bash reads the `#<epoch>` line in front of each command back as its timestamp
+HISTTIMEFORMAT="${HISTTIMEFORMAT:-%F %T }"
+history_tidy_session="$$-$(date +%s)"
+# `history.file` in config.toml, which `history-tidy -init-bash` sets below when it is changed
+history_tidy_history_path="$HOME/.history-tidy/history"
+
+# Runs the command picked in history-tidy, which has no hashtags, and adds it to the history as
+# `script_history` says, since `eval` in a prompt hook doesn't add it
+history_tidy_exec () {
+    local history_tidy_start
+    local history_tidy_cwd="$PWD"
+    # into bash's own history too, under the number the prompt hook then expects, and with the
+    # timestamp bash gave it, so both histories say the same
+    builtin history -s -- "${2:-$1}"
+    local history_pattern='^ *([0-9]+). ([0-9]+) '
+    if [[ $(HISTTIMEFORMAT='%s ' builtin history 1) =~ $history_pattern ]]
+    then
+        history_tidy_history_number="${BASH_REMATCH[1]}"
+        history_tidy_start="${BASH_REMATCH[2]}"
+    else
+        history_tidy_start=$(date +%s)
+    fi
+    eval "$1"
+    local exit_status=$?
+    printf '#%s\t%s\t%s\t%s\t%s\n%s\n' "$history_tidy_start" "$exit_status" "$HOSTNAME" \
+        "$history_tidy_session" "$history_tidy_cwd" "${2:-$1}" >> "$history_tidy_history_path"
+    return $exit_status
+}
+
 history_tidy_prompt () {
+    # must come first, anything run below overwrites it
+    local exit_status=$?
     local history_tidy_dict_path="$HOME/.history-tidy"
 
     if [ ! -d "$history_tidy_dict_path" ]; then
         mkdir "$history_tidy_dict_path"
     fi
 
-    local history_path="$history_tidy_dict_path/history"
-    history -a $history_path;
-    history -cr $history_path;
+    local history_path="$history_tidy_history_path"
+    # `history 1` prints "  <number>  <epoch> <command>", the number only changes when a command
+    # was added
+    local history_pattern='^ *([0-9]+). ([0-9]+) (.*)$'
+    local last_history=$(HISTTIMEFORMAT='%s ' builtin history 1)
+    if [[ $last_history =~ $history_pattern ]]
+    then
+        if [ -n "$history_tidy_history_number" ] \
+            && [ "${BASH_REMATCH[1]}" != "$history_tidy_history_number" ]
+        then
+            # `#<epoch>\t<exit status>\t<hostname>\t<session id>\t<cwd>`, then the command
+            printf '#%s\t%s\t%s\t%s\t%s\n%s\n' "${BASH_REMATCH[2]}" "$exit_status" "$HOSTNAME" \
+                "$history_tidy_session" "$history_tidy_pwd" "${BASH_REMATCH[3]}" >> "$history_path"
+        fi
+        history_tidy_history_number="${BASH_REMATCH[1]}"
+    else
+        history_tidy_history_number=0
+    fi
+    # the next command is run from here
+    history_tidy_pwd="$PWD"
     local exec_command=$(cat $history_tidy_dict_path/script);
 
     if [ -z "$exec_command" ]
@@ -18,6 +66,16 @@ history_tidy_prompt () {
     fi
     
     echo > $history_tidy_dict_path/script
+    local history_command=$(cat $history_tidy_dict_path/script_history 2> /dev/null)
+
+    # `confirm = false` in config.toml runs the command without asking
+    if [ "$(cat $history_tidy_dict_path/script_confirm 2> /dev/null)" = "no" ]
+    then
+        echo $exec_command
+        history_tidy_exec "$exec_command" "$history_command";
+        history_tidy_status=$?
+        return $history_tidy_status;
+    fi
 
     while :
     do
@@ -25,7 +83,7 @@ history_tidy_prompt () {
         read -p "Do you exec this command? [Y/n] " choice
         case "$choice" in
             [Yy])
-                eval $exec_command;
+                history_tidy_exec "$exec_command" "$history_command";
                 history_tidy_status=$?
                 return $?;
                 ;;
@@ -37,7 +95,7 @@ history_tidy_prompt () {
             *)
                 if [ -z "$choice" ]
                 then
-                    eval $exec_command;
+                    history_tidy_exec "$exec_command" "$history_command";
                     history_tidy_status=$?
                     return $?;
                 fi
@@ -46,6 +104,27 @@ history_tidy_prompt () {
     done
 }
 
+# bash's history stays its own, history-tidy reads $HISTFILE along with its history file, see
+# `history.sources` in config.toml. Only history-tidy is given it, not the commands it runs.
+history-tidy () {
+    HISTFILE="$HISTFILE" command history-tidy "$@"
+}
+
+# Ctrl-G puts the command picked in history-tidy on the command line, to edit or run it with Enter
+history_tidy_widget () {
+    local command=$(history-tidy --print)
+    if [ -n "$command" ]
+    then
+        READLINE_LINE="$command"
+        READLINE_POINT=${#READLINE_LINE}
+    fi
+}
+
+if [[ $- == *i* ]]
+then
+    bind -x '"\C-g": history_tidy_widget'
+fi
+
 if [ -z "$PROMPT_COMMAND" ]
     then
         # not already set
@@ -0,0 +1,112 @@
+#!/usr/bin/fish
+set -g history_tidy_session "$fish_pid-"(date +%s)
+# `history.file` in config.toml, which `history-tidy -init-fish` sets below when it is changed
+set -g history_tidy_history_path "$HOME/.history-tidy/history"
+
+function history_tidy_preexec --on-event fish_preexec
+    # the command may change the directory, so remember where it was run from
+    set -g history_tidy_pwd "$PWD"
+    set -g history_tidy_start (date +%s)
+end
+
+function history_tidy_postexec --on-event fish_postexec
+    # must come first, anything run below overwrites it
+    set -l exit_status $status
+    set -l history_tidy_dict_path "$HOME/.history-tidy"
+
+    if not test -d "$history_tidy_dict_path"
+        mkdir "$history_tidy_dict_path"
+    end
+
+    if test -z "$argv[1]"
+        return 0
+    end
+
+    # `#<epoch>\t<exit status>\t<hostname>\t<session id>\t<cwd>`, then the command in the format of
+    # fish_history: backslashes are doubled and new lines become "\n"
+    set -l command (string replace -a -- '\\' '\\\\' "$argv[1]" | string join '\n')
+    printf -- '#%s\t%s\t%s\t%s\t%s\n- cmd: %s\n  when: %s\n' "$history_tidy_start" "$exit_status" \
+        "$hostname" "$history_tidy_session" "$history_tidy_pwd" "$command" "$history_tidy_start" \
+        >> "$history_tidy_history_path"
+end
+
+# Runs the command picked in history-tidy, which has no hashtags, and adds it to the history as
+# `script_history` says, since `eval` in an event handler doesn't add it
+function history_tidy_exec
+    set -l history_tidy_start (date +%s)
+    set -l history_tidy_cwd "$PWD"
+    eval $argv[1]
+    set -l exit_status $status
+    set -l history_command $argv[2]
+    if test -z "$history_command"
+        set history_command $argv[1]
+    end
+    set -l command (string replace -a -- '\\' '\\\\' "$history_command" | string join '\n')
+    printf -- '#%s\t%s\t%s\t%s\t%s\n- cmd: %s\n  when: %s\n' "$history_tidy_start" "$exit_status" \
+        "$hostname" "$history_tidy_session" "$history_tidy_cwd" "$command" "$history_tidy_start" \
+        >> "$history_tidy_history_path"
+    return $exit_status
+end
+
+function history_tidy_prompt --on-event fish_prompt
+    set -l history_tidy_dict_path "$HOME/.history-tidy"
+
+    if not test -f "$history_tidy_dict_path/script"
+        return 0
+    end
+
+    set -l exec_command (cat "$history_tidy_dict_path/script" | string collect)
+
+    if test -z "$exec_command"
+        return 0
+    end
+
+    echo > "$history_tidy_dict_path/script"
+    set -l history_command (cat "$history_tidy_dict_path/script_history" 2> /dev/null | string collect)
+
+    # `confirm = false` in config.toml runs the command without asking
+    set -l confirm (cat "$history_tidy_dict_path/script_confirm" 2> /dev/null)
+    if test "$confirm" = no
+        echo $exec_command
+        history_tidy_exec "$exec_command" "$history_command"
+        return $status
+    end
+
+    while true
+        echo $exec_command
+        read -P "Do you exec this command? [Y/n] " choice
+        switch "$choice"
+            case Y y ''
+                history_tidy_exec "$exec_command" "$history_command"
+                return $status
+            case N n
+                echo Abort.
+                return 1
+        end
+    end
+end
+
+# history-tidy reads fish's own history along with its history file as $HISTFILE, see
+# `history.sources` in config.toml
+function history-tidy --wraps history-tidy
+    set -l data_path "$XDG_DATA_HOME"
+    if test -z "$data_path"
+        set data_path "$HOME/.local/share"
+    end
+    set -l session fish
+    if set -q fish_history; and test -n "$fish_history"
+        set session $fish_history
+    end
+    env HISTFILE="$data_path/fish/$session"_history history-tidy $argv
+end
+
+# Ctrl-G puts the command picked in history-tidy on the command line, to edit or run it with Enter
+function history_tidy_widget
+    set -l command (history-tidy --print | string collect)
+    if test -n "$command"
+        commandline -r -- $command
+    end
+    commandline -f repaint
+end
+
+bind \cg history_tidy_widget
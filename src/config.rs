@@ -0,0 +1,561 @@
+use crate::keymap::Action;
+use crate::keymap::KeyBinding;
+use crate::keymap::KeyPreset;
+use crate::keymap::Keymap;
+use crate::parse_history::get_tidy_dir_path;
+use dirs::home_dir;
+use serde::de;
+use serde::de::MapAccess;
+use serde::de::Visitor;
+use serde::Deserialize;
+use serde::Deserializer;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::env::var;
+use std::fmt;
+use std::fs::read_to_string;
+use std::fs::write;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::path::PathBuf;
+use toml_edit::DocumentMut;
+use tui::style::Color;
+
+// ~/.history-tidy/config.toml, every key is optional and an unknown one is an error, e.g.
+//
+//   [history]
+//   file = "~/.history-tidy/history"
+//   sources = ["$HISTFILE", "~/.zsh_history"]
+//   ignore = ["ls", "cd *"]
+//
+//   [hashtag]
+//   fold_case = true
+//   nfkc = true
+//
+//   [hashtag.aliases]
+//   "#dkr" = "#docker"
+//
+//   [ui]
+//   default_sort = "recency"
+//
+//   [ui.theme]
+//   highlight = "magenta"
+//
+//   [keys]
+//   preset = "vim"
+//   quit = ["q", "<Esc>"]
+//
+//   [run]
+//   keep_hashtags = false
+//   confirm = false
+#[derive(PartialEq, Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub history: HistoryConfig,
+    pub hashtag: HashtagConfig,
+    pub ui: UiConfig,
+    pub keys: KeysConfig,
+    pub run: RunConfig,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    // read and rewritten by history-tidy, and appended to by the init scripts
+    pub file: String,
+    // other history files read along with it but never rewritten, such as the shell's own
+    pub sources: Vec<String>,
+    // commands left out of every view, "*" matches any text and "?" any character
+    pub ignore: Vec<String>,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> HistoryConfig {
+        return HistoryConfig {
+            file: "~/.history-tidy/history".to_owned(),
+            // passed on by the init scripts
+            sources: vec!["$HISTFILE".to_owned()],
+            ignore: vec![],
+        };
+    }
+}
+
+impl HistoryConfig {
+    pub fn get_file_path(&self) -> Result<PathBuf, Error> {
+        return expand_home(&self.file);
+    }
+
+    // A source naming an unset environment variable, like $HISTFILE outside the shell, is left out.
+    pub fn get_source_paths(&self) -> Result<Vec<PathBuf>, Error> {
+        let mut source_paths: Vec<PathBuf> = vec![];
+        for source in &self.sources {
+            if let Some(source_path) = expand_env(source)? {
+                source_paths.push(source_path);
+            }
+        }
+        return Ok(source_paths);
+    }
+}
+
+// "$HISTFILE" and "$XDG_DATA_HOME/fish/fish_history" take the variable from the environment.
+fn expand_env(path: &str) -> Result<Option<PathBuf>, Error> {
+    let rest: &str = match path.strip_prefix('$') {
+        Some(rest) => rest,
+        None => return Ok(Some(expand_home(path)?)),
+    };
+    let (name, rest): (&str, &str) = match rest.find('/') {
+        Some(index) => (&rest[..index], &rest[index..]),
+        None => (rest, ""),
+    };
+    match var(name) {
+        Ok(value) if value.len() > 0 => return Ok(Some(expand_home(&(value + rest))?)),
+        _ => return Ok(None),
+    }
+}
+
+// "~/x" is x in the home directory.
+pub fn expand_home(path: &str) -> Result<PathBuf, Error> {
+    let rest: &str = match path.strip_prefix('~') {
+        Some(rest) if rest.len() == 0 || rest.starts_with('/') => rest.trim_start_matches('/'),
+        _ => return Ok(PathBuf::from(path)),
+    };
+    match home_dir() {
+        Some(home) => return Ok(home.join(rest)),
+        None => return Err(Error::new(ErrorKind::NotFound, "Can't get home path")),
+    }
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HashtagConfig {
+    // "#Docker" and "#docker" are the same hashtag
+    pub fold_case: bool,
+    // "#ｄｏｃｋｅｒ" and "#docker" are the same hashtag
+    pub nfkc: bool,
+    // synonym -> canonical hashtag
+    pub aliases: BTreeMap<String, String>,
+}
+
+impl Default for HashtagConfig {
+    fn default() -> HashtagConfig {
+        return HashtagConfig {
+            fold_case: true,
+            nfkc: false,
+            aliases: BTreeMap::new(),
+        };
+    }
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Frecency,
+    Recency,
+    Frequency,
+    Alphabetical,
+}
+
+#[derive(PartialEq, Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UiConfig {
+    // how the Command View is sorted when it is opened
+    pub default_sort: SortOrder,
+    pub theme: ThemeConfig,
+}
+
+impl Default for UiConfig {
+    fn default() -> UiConfig {
+        return UiConfig {
+            default_sort: SortOrder::Frecency,
+            theme: ThemeConfig::default(),
+        };
+    }
+}
+
+// A color name such as "yellow" or "lightblue", or "#rrggbb".
+#[derive(PartialEq, Debug, Clone, Copy, Deserialize)]
+#[serde(try_from = "String")]
+pub struct ThemeColor(pub Color);
+
+impl TryFrom<String> for ThemeColor {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<ThemeColor, String> {
+        let color: Color = match name.to_lowercase().as_str() {
+            "black" => Color::Black,
+            "red" => Color::Red,
+            "green" => Color::Green,
+            "yellow" => Color::Yellow,
+            "blue" => Color::Blue,
+            "magenta" => Color::Magenta,
+            "cyan" => Color::Cyan,
+            "gray" => Color::Gray,
+            "darkgray" => Color::DarkGray,
+            "lightred" => Color::LightRed,
+            "lightgreen" => Color::LightGreen,
+            "lightyellow" => Color::LightYellow,
+            "lightblue" => Color::LightBlue,
+            "lightmagenta" => Color::LightMagenta,
+            "lightcyan" => Color::LightCyan,
+            "white" => Color::White,
+            hex if hex.len() == 7 && hex.starts_with('#') => {
+                match u32::from_str_radix(&hex[1..], 16) {
+                    Ok(rgb) => Color::Rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8),
+                    Err(_) => return Err(format!("invalid color '{}'", name)),
+                }
+            }
+            _ => return Err(format!("invalid color '{}'", name)),
+        };
+        return Ok(ThemeColor(color));
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThemeConfig {
+    // search matches and prompts
+    pub highlight: ThemeColor,
+    // names of the actions in the help lines
+    pub key: ThemeColor,
+    // exit status, cwd and hostname of the selected command
+    pub details: ThemeColor,
+    pub error: ThemeColor,
+}
+
+impl Default for ThemeConfig {
+    fn default() -> ThemeConfig {
+        return ThemeConfig {
+            highlight: ThemeColor(Color::Yellow),
+            key: ThemeColor(Color::Green),
+            details: ThemeColor(Color::Cyan),
+            error: ThemeColor(Color::Red),
+        };
+    }
+}
+
+// A preset with the keys of some actions replaced, e.g. `preset = "vim"` and
+// `quit = ["q", "<Esc>"]`.
+#[derive(PartialEq, Debug, Clone)]
+pub struct KeysConfig {
+    pub preset: KeyPreset,
+    pub bindings: BTreeMap<Action, KeyBinding>,
+}
+
+// A key of the [keys] table, checked as it is read so that an error has its line number.
+enum KeysField {
+    Preset,
+    Action(Action),
+}
+
+impl<'de> Deserialize<'de> for KeysField {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<KeysField, D::Error> {
+        struct KeysFieldVisitor;
+
+        impl<'de> Visitor<'de> for KeysFieldVisitor {
+            type Value = KeysField;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                return formatter.write_str("`preset` or an action");
+            }
+
+            fn visit_str<E: de::Error>(self, name: &str) -> Result<KeysField, E> {
+                if name == "preset" {
+                    return Ok(KeysField::Preset);
+                }
+                match Action::from_name(name) {
+                    Some(action) => return Ok(KeysField::Action(action)),
+                    None => {
+                        return Err(E::custom(format!(
+                            "unknown field `{}`, expected `preset` or an action like `move_down`",
+                            name
+                        )))
+                    }
+                }
+            }
+        }
+
+        return deserializer.deserialize_identifier(KeysFieldVisitor);
+    }
+}
+
+// Read by hand since the actions are keys of the same table as `preset`, which serde's flatten
+// would read without the line numbers of errors.
+impl<'de> Deserialize<'de> for KeysConfig {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<KeysConfig, D::Error> {
+        struct KeysConfigVisitor;
+
+        impl<'de> Visitor<'de> for KeysConfigVisitor {
+            type Value = KeysConfig;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                return formatter.write_str("a table of key bindings");
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<KeysConfig, A::Error> {
+                let mut keys_config: KeysConfig = KeysConfig::default();
+                while let Some(field) = map.next_key::<KeysField>()? {
+                    match field {
+                        KeysField::Preset => keys_config.preset = map.next_value()?,
+                        KeysField::Action(action) => {
+                            keys_config.bindings.insert(action, map.next_value()?);
+                        }
+                    }
+                }
+                return Ok(keys_config);
+            }
+        }
+
+        return deserializer.deserialize_map(KeysConfigVisitor);
+    }
+}
+
+impl Default for KeysConfig {
+    fn default() -> KeysConfig {
+        return KeysConfig {
+            preset: KeyPreset::Default,
+            bindings: BTreeMap::new(),
+        };
+    }
+}
+
+impl KeysConfig {
+    pub fn get_keymap(&self) -> Keymap {
+        return Keymap::new(self.preset, &self.bindings);
+    }
+}
+
+// How a command picked in history-tidy is run.
+#[derive(Eq, PartialEq, Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RunConfig {
+    // the command always runs without its hashtags, this is whether the history and the output of
+    // `--print` keep them
+    pub keep_hashtags: bool,
+    // whether the shell asks "Do you exec this command? [Y/n]" before running it
+    pub confirm: bool,
+}
+
+impl Default for RunConfig {
+    fn default() -> RunConfig {
+        return RunConfig {
+            keep_hashtags: true,
+            confirm: true,
+        };
+    }
+}
+
+pub fn get_config_file_path() -> Result<PathBuf, Error> {
+    return Ok(get_tidy_dir_path()?.join("config.toml"));
+}
+
+// A missing config file is the same as an empty one.
+pub fn get_config() -> Result<Config, Error> {
+    return load_config(&get_config_file_path()?);
+}
+
+pub fn load_config(config_path: &Path) -> Result<Config, Error> {
+    match read_to_string(config_path) {
+        Ok(content) => return parse_config(&content),
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Config::default()),
+        Err(e) => return Err(e),
+    }
+}
+
+pub fn parse_config(content: &str) -> Result<Config, Error> {
+    let config: Config = toml::from_str(content).map_err(|e: toml::de::Error| {
+        // e.g. "config.toml:3: unknown field `sort`, expected `default_sort` or `theme`"
+        let location: String = match e.span() {
+            Some(span) => format!(":{}", content[..span.start].matches('\n').count() + 1),
+            None => String::new(),
+        };
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("config.toml{}: {}", location, e.message().trim_end()),
+        )
+    })?;
+    config
+        .keys
+        .get_keymap()
+        .check()
+        .map_err(|message: String| {
+            Error::new(ErrorKind::InvalidData, format!("config.toml: {}", message))
+        })?;
+    return Ok(config);
+}
+
+pub fn add_hashtag_alias(alias: &str, hashtag_name: &str) -> Result<(), Error> {
+    return write_hashtag_alias(&get_config_file_path()?, alias, hashtag_name);
+}
+
+// Edits the file in place, so the user's comments and layout are kept.
+pub fn write_hashtag_alias(
+    config_path: &Path,
+    alias: &str,
+    hashtag_name: &str,
+) -> Result<(), Error> {
+    let content: String = match read_to_string(config_path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    let invalid_config = |message: String| -> Error {
+        return Error::new(ErrorKind::InvalidData, format!("config.toml: {}", message));
+    };
+    let mut document: DocumentMut = content
+        .parse()
+        .map_err(|e: toml_edit::TomlError| invalid_config(e.message().to_owned()))?;
+
+    let hashtag_table: &mut toml_edit::Table = document
+        .entry("hashtag")
+        .or_insert_with(|| {
+            let mut table: toml_edit::Table = toml_edit::Table::new();
+            table.set_implicit(true);
+            toml_edit::Item::Table(table)
+        })
+        .as_table_mut()
+        .ok_or_else(|| invalid_config("'hashtag' is not a table".to_owned()))?;
+    let alias_table: &mut toml_edit::Table = hashtag_table
+        .entry("aliases")
+        .or_insert(toml_edit::table())
+        .as_table_mut()
+        .ok_or_else(|| invalid_config("'hashtag.aliases' is not a table".to_owned()))?;
+    alias_table.insert(alias, toml_edit::value(hashtag_name));
+
+    return write(config_path, document.to_string());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::temp_dir;
+    use std::fs::remove_file;
+
+    #[test]
+    fn parse_config_test() {
+        assert_eq!(parse_config("").unwrap(), Config::default());
+
+        let config: Config =
+            parse_config("[hashtag]\nnfkc = true\n\n[hashtag.aliases]\n\"#dkr\" = \"#docker\"\n")
+                .unwrap();
+        assert!(config.hashtag.fold_case);
+        assert!(config.hashtag.nfkc);
+        assert_eq!(config.hashtag.aliases["#dkr"], "#docker");
+
+        assert!(config.run.keep_hashtags);
+        assert!(
+            !parse_config("[run]\nkeep_hashtags = false\n")
+                .unwrap()
+                .run
+                .keep_hashtags
+        );
+
+        assert!(parse_config("[hashtag]\nnfkc = \"yes\"\n").is_err());
+    }
+
+    #[test]
+    fn parse_typed_config_test() {
+        let config: Config = parse_config(
+            "[history]\nfile = \"/tmp/history\"\nignore = [\"ls\"]\n\n\
+             [ui]\ndefault_sort = \"recency\"\n\n[ui.theme]\nhighlight = \"#ff8000\"\n\n\
+             [keys]\nquit = \"x\"\n\n[run]\nconfirm = false\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config.history.get_file_path().unwrap(),
+            PathBuf::from("/tmp/history")
+        );
+        assert_eq!(config.history.ignore, ["ls"]);
+        assert_eq!(config.history.sources, ["$HISTFILE"]);
+        assert_eq!(config.ui.default_sort, SortOrder::Recency);
+        assert_eq!(
+            config.ui.theme.highlight,
+            ThemeColor(Color::Rgb(255, 128, 0))
+        );
+        assert_eq!(config.ui.theme.key, ThemeColor(Color::Green));
+        let keymap: Keymap = config.keys.get_keymap();
+        assert_eq!(keymap.get_key_name(Action::Quit), "'x'");
+        assert_eq!(keymap.get_key_name(Action::Edit), "'e'");
+        assert!(!config.run.confirm);
+        assert!(config.run.keep_hashtags);
+
+        let error_message = |content: &str| -> String {
+            return parse_config(content).unwrap_err().to_string();
+        };
+        assert_eq!(
+            error_message("[ui]\n\nsort = \"recency\"\n"),
+            "config.toml:3: unknown field `sort`, expected `default_sort` or `theme`"
+        );
+        assert!(error_message("[colors]\n").starts_with("config.toml:1: unknown field `colors`"));
+        assert!(error_message("[ui]\ndefault_sort = \"random\"\n")
+            .starts_with("config.toml:2: unknown variant `random`"));
+        assert_eq!(
+            error_message("[ui.theme]\nkey = \"pink\"\n"),
+            "config.toml:2: invalid color 'pink'"
+        );
+        assert_eq!(
+            error_message("[keys]\ntag = \"e\"\n"),
+            "config.toml: keys: 'e' is bound to both edit and tag"
+        );
+        assert!(error_message("[keys]\npreset = \"vi\"\n")
+            .starts_with("config.toml:2: unknown variant `vi`"));
+        assert!(error_message("[keys]\n\nmove_left = \"h\"\n")
+            .starts_with("config.toml:3: unknown field `move_left`"));
+        assert_eq!(
+            error_message("[keys]\nquit = \"<Foo>\"\n"),
+            "config.toml:2: invalid key '<Foo>'"
+        );
+
+        let config: Config =
+            parse_config("[keys]\npreset = \"vim\"\nmove_top = [\"<Home>\"]\n").unwrap();
+        let keymap: Keymap = config.keys.get_keymap();
+        assert_eq!(keymap.get_key_name(Action::MoveDown), "'j'");
+        assert_eq!(keymap.get_key_name(Action::MoveTop), "Home");
+    }
+
+    #[test]
+    fn get_source_paths_test() {
+        std::env::set_var("HISTORY_TIDY_TEST_DATA", "/data");
+        std::env::remove_var("HISTORY_TIDY_TEST_UNSET");
+        let history_config: HistoryConfig = HistoryConfig {
+            sources: vec![
+                "$HISTORY_TIDY_TEST_DATA/fish/fish_history".to_owned(),
+                "$HISTORY_TIDY_TEST_UNSET".to_owned(),
+                "/tmp/history".to_owned(),
+            ],
+            ..HistoryConfig::default()
+        };
+        assert_eq!(
+            history_config.get_source_paths().unwrap(),
+            [
+                PathBuf::from("/data/fish/fish_history"),
+                PathBuf::from("/tmp/history")
+            ]
+        );
+    }
+
+    #[test]
+    fn write_hashtag_alias_test() {
+        let config_path: PathBuf =
+            temp_dir().join(format!("history-tidy-config-{}.toml", std::process::id()));
+        write(&config_path, "# my settings\n[hashtag]\nnfkc = true\n").unwrap();
+
+        write_hashtag_alias(&config_path, "#dkr", "#docker").unwrap();
+        write_hashtag_alias(&config_path, "#k8", "#k8s").unwrap();
+        let content: String = read_to_string(&config_path).unwrap();
+        assert!(content.starts_with("# my settings\n"));
+
+        let config: Config = load_config(&config_path).unwrap();
+        assert!(config.hashtag.nfkc);
+        assert_eq!(config.hashtag.aliases["#dkr"], "#docker");
+        assert_eq!(config.hashtag.aliases["#k8"], "#k8s");
+
+        remove_file(&config_path).unwrap();
+        write_hashtag_alias(&config_path, "#dkr", "#docker").unwrap();
+        assert_eq!(
+            load_config(&config_path).unwrap().hashtag.aliases["#dkr"],
+            "#docker"
+        );
+        remove_file(&config_path).unwrap();
+    }
+}
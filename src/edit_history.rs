@@ -0,0 +1,541 @@
+use crate::config::HistoryConfig;
+use crate::hashtag::find_shell_comment;
+use crate::hashtag::Hashtag;
+use crate::hashtag::HashtagParser;
+use crate::index_cache::get_history_paths;
+use crate::index_cache::invalidate_command_index;
+use crate::normalize::HashtagNormalizer;
+use crate::parse_history::get_tidy_history;
+use crate::parse_history::parse_history;
+use crate::parse_history::HistoryEntry;
+use crate::parse_history::HistoryFormat;
+use crate::parse_history::HistoryRecord;
+use crate::utils::get_unix_time;
+use std::collections::HashSet;
+use std::fs::read_to_string;
+use std::fs::rename;
+use std::fs::set_permissions;
+use std::fs::write;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::fs::Permissions;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+// The other history sources, like $HISTFILE, are only read. A command that changes there is
+// copied to the history file instead, rewritten and under the time of each run, so the index
+// counts the copy in place of the run in the source.
+pub fn rewrite_history<F: Fn(&str) -> String>(
+    rewrite_command: F,
+    history_config: &HistoryConfig,
+) -> Result<(), Error> {
+    let history_paths: Vec<PathBuf> = get_history_paths(history_config)?;
+    let history_file_path: &Path = &history_paths[0];
+    let mut changed: bool = rewrite_history_file(history_file_path, &rewrite_command)?;
+
+    let mut source_records: Vec<HistoryRecord> = vec![];
+    for source_path in &history_paths[1..] {
+        source_records.extend(parse_history(&read_history_file(source_path)?));
+    }
+    let history_file_content: String = read_history_file(history_file_path)?;
+    let copied_records: Vec<HistoryRecord> =
+        get_copied_records(&history_file_content, source_records, &rewrite_command);
+    if copied_records.len() > 0 {
+        let mut history_file: File = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(history_file_path)?;
+        if history_file_content.len() > 0 && !history_file_content.ends_with('\n') {
+            history_file.write_all(b"\n")?;
+        }
+        for history_record in copied_records {
+            history_file.write_all((history_record.to_history_string() + "\n").as_bytes())?;
+        }
+        changed = true;
+    }
+
+    if changed {
+        invalidate_command_index()?;
+    }
+    return Ok(());
+}
+
+// A history file that doesn't exist yet is empty.
+fn read_history_file(history_path: &Path) -> Result<String, Error> {
+    match read_to_string(history_path) {
+        Ok(content) => return Ok(content),
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(String::new()),
+        Err(e) => return Err(e),
+    }
+}
+
+// The runs in the other sources that `rewrite_command` changes and the history file doesn't have,
+// as records for it. A run the history file has too, which is the same command at the same time,
+// or any run of the command without a time, is already counted from the history file.
+fn get_copied_records<F: Fn(&str) -> String>(
+    history_file_content: &str,
+    source_records: Vec<HistoryRecord>,
+    rewrite_command: F,
+) -> Vec<HistoryRecord> {
+    let mut recorded_runs: HashSet<(String, Option<i64>)> = HashSet::new();
+    for entry in get_tidy_history(history_file_content) {
+        let command: String = strip_hashtags(&entry.command);
+        recorded_runs.insert((command.to_owned(), None));
+        recorded_runs.insert((command, entry.timestamp));
+    }
+
+    let mut copied_records: Vec<HistoryRecord> = vec![];
+    for history_record in source_records {
+        let command: &str = history_record.entry.command.trim();
+        let new_command: String = rewrite_command(command);
+        let run: (String, Option<i64>) = (strip_hashtags(command), history_record.entry.timestamp);
+        if new_command == command || recorded_runs.contains(&run) {
+            continue;
+        }
+        // zsh's format, which keeps the time and a multi-line command
+        let timestamp: i64 = history_record.entry.timestamp.unwrap_or_else(get_unix_time);
+        copied_records.push(HistoryRecord {
+            format: HistoryFormat::Zsh,
+            prefix: format!(": {}:0;", timestamp),
+            entry: HistoryEntry::new(&new_command),
+            suffix: String::new(),
+        });
+    }
+    return copied_records;
+}
+
+// Returns whether any command changed.
+fn rewrite_history_file<F: Fn(&str) -> String>(
+    history_file_path: &Path,
+    rewrite_command: F,
+) -> Result<bool, Error> {
+    let history_file_content: String = read_history_file(history_file_path)?;
+
+    let mut changed: bool = false;
+    let mut new_history_file_content: String = String::new();
+    for mut history_record in parse_history(&history_file_content) {
+        let new_command: String = rewrite_command(&history_record.entry.command);
+        if new_command != history_record.entry.command {
+            changed = true;
+            history_record.entry.command = new_command;
+        }
+        new_history_file_content.push_str(&history_record.to_history_string());
+        new_history_file_content.push('\n');
+    }
+
+    if changed {
+        replace_history_file(
+            history_file_path,
+            history_file_content.len() as u64,
+            new_history_file_content,
+        )?;
+    }
+    return Ok(changed);
+}
+
+// The shells append to the history file at every prompt, so it is replaced in one step rather
+// than truncated and written again, and the lines appended since it was read at `read_len` are
+// carried over. A crash leaves the old file.
+fn replace_history_file(
+    history_file_path: &Path,
+    read_len: u64,
+    new_history_file_content: String,
+) -> Result<(), Error> {
+    let mut content: Vec<u8> = new_history_file_content.into_bytes();
+    let mut history_file: File = File::open(history_file_path)?;
+    let permissions: Permissions = history_file.metadata()?.permissions();
+    history_file.seek(SeekFrom::Start(read_len))?;
+    history_file.read_to_end(&mut content)?;
+
+    let tmp_path: PathBuf = history_file_path.with_extension("tmp");
+    write(&tmp_path, content)?;
+    set_permissions(&tmp_path, permissions)?;
+    return rename(tmp_path, history_file_path);
+}
+
+// `old_hashtag` is a normalized name, so "#Docker" and its aliases are all replaced.
+pub fn replace_hashtag(
+    command: &str,
+    old_hashtag: &str,
+    new_hashtag: &str,
+    normalizer: &HashtagNormalizer,
+) -> String {
+    let hashtags: Vec<Hashtag> = HashtagParser::new_shell(command).collect::<Vec<Hashtag>>();
+    let chars: Vec<char> = command.chars().collect();
+
+    let mut new_command: String = String::new();
+    let mut index: usize = 0;
+    for hashtag in hashtags {
+        if normalizer.normalize_hashtag(&hashtag) != old_hashtag {
+            continue;
+        }
+        new_command.extend(&chars[index..hashtag.start]);
+        new_command.push_str(new_hashtag);
+        index = hashtag.end + 1;
+    }
+    new_command.extend(&chars[index..]);
+    return new_command;
+}
+
+pub fn remove_hashtag(command: &str, hashtag_name: &str, normalizer: &HashtagNormalizer) -> String {
+    return remove_comment_hashtags(command, |hashtag: &Hashtag| {
+        normalizer.normalize_hashtag(hashtag) == hashtag_name
+    });
+}
+
+// The command as it is run, which unlike the line in the history has no hashtags.
+pub fn strip_hashtags(command: &str) -> String {
+    return remove_comment_hashtags(command, |_: &Hashtag| true)
+        .trim()
+        .to_owned();
+}
+
+// A comment left with only hashtags removed is dropped, while any other text in it stays behind
+// the `#`, so "make #note rm -rf build" becomes "make # rm -rf build" rather than running `rm`.
+fn remove_comment_hashtags<F: Fn(&Hashtag) -> bool>(command: &str, is_removed: F) -> String {
+    let comment_start: usize = match find_shell_comment(command) {
+        Some((_, char_index)) => char_index,
+        None => return command.to_owned(),
+    };
+    let hashtags: Vec<Hashtag> = HashtagParser::new_shell(command).collect::<Vec<Hashtag>>();
+    let chars: Vec<char> = command.chars().collect();
+
+    let mut comment: String = String::new();
+    let mut index: usize = comment_start;
+    let mut removed: bool = false;
+    for hashtag in hashtags {
+        if !is_removed(&hashtag) {
+            continue;
+        }
+        let mut start: usize = hashtag.start;
+        while start > index && chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        comment.extend(&chars[index..start]);
+        index = hashtag.end + 1;
+        removed = true;
+    }
+    if !removed {
+        return command.to_owned();
+    }
+    comment.extend(&chars[index..]);
+
+    let new_command: String = chars[..comment_start].iter().collect::<String>();
+    let new_command: &str = new_command.trim();
+    let comment: &str = comment.trim();
+    if comment.len() == 0 {
+        return new_command.to_owned();
+    }
+    if comment.starts_with('#') {
+        return format!("{} {}", new_command, comment).trim().to_owned();
+    }
+    return format!("{} # {}", new_command, comment).trim().to_owned();
+}
+
+pub fn parse_hashtag_input(input: &str) -> Option<Vec<String>> {
+    let hashtags: Vec<Hashtag> = HashtagParser::new(input).collect::<Vec<Hashtag>>();
+    let chars: Vec<char> = input.chars().collect();
+
+    let mut index: usize = 0;
+    let mut hashtag_names: Vec<String> = vec![];
+    for hashtag in hashtags {
+        if chars[index..hashtag.start]
+            .iter()
+            .any(|c: &char| !c.is_whitespace())
+        {
+            return None;
+        }
+        hashtag_names.push(hashtag.to_string());
+        index = hashtag.end + 1;
+    }
+    if hashtag_names.len() == 0 || chars[index..].iter().any(|c: &char| !c.is_whitespace()) {
+        return None;
+    }
+    return Some(hashtag_names);
+}
+
+pub fn append_hashtags(
+    command: &str,
+    hashtag_names: &[String],
+    normalizer: &HashtagNormalizer,
+) -> String {
+    let mut current_hashtags: Vec<String> = HashtagParser::new_shell(command)
+        .map(|hashtag: Hashtag| normalizer.normalize_hashtag(&hashtag))
+        .collect();
+
+    let mut new_command: String = command.trim_end().to_owned();
+    for hashtag_name in hashtag_names {
+        let normalized_name: String = normalizer.normalize(hashtag_name);
+        if current_hashtags.contains(&normalized_name) {
+            continue;
+        }
+        new_command.push(' ');
+        new_command.push_str(hashtag_name);
+        current_hashtags.push(normalized_name);
+    }
+    return new_command;
+}
+
+pub fn rename_hashtag(
+    old_hashtag: &str,
+    new_hashtag: &str,
+    normalizer: &HashtagNormalizer,
+    history_config: &HistoryConfig,
+) -> Result<(), Error> {
+    return rewrite_history(
+        |history: &str| replace_hashtag(history, old_hashtag, new_hashtag, normalizer),
+        history_config,
+    );
+}
+
+pub fn delete_hashtag(
+    hashtag_name: &str,
+    normalizer: &HashtagNormalizer,
+    history_config: &HistoryConfig,
+) -> Result<(), Error> {
+    return rewrite_history(
+        |history: &str| remove_hashtag(history, hashtag_name, normalizer),
+        history_config,
+    );
+}
+
+pub fn detach_hashtags(
+    command: &str,
+    hashtag_names: &[String],
+    normalizer: &HashtagNormalizer,
+    history_config: &HistoryConfig,
+) -> Result<(), Error> {
+    return rewrite_history(
+        |history: &str| {
+            if history.trim() != command {
+                return history.to_owned();
+            }
+            let mut new_history: String = history.to_owned();
+            for hashtag_name in hashtag_names {
+                new_history = remove_hashtag(&new_history, hashtag_name, normalizer);
+            }
+            new_history
+        },
+        history_config,
+    );
+}
+
+pub fn add_hashtags(
+    command: &str,
+    hashtag_names: &[String],
+    normalizer: &HashtagNormalizer,
+    history_config: &HistoryConfig,
+) -> Result<(), Error> {
+    return rewrite_history(
+        |history: &str| {
+            if history.trim() == command {
+                append_hashtags(history, hashtag_names, normalizer)
+            } else {
+                history.to_owned()
+            }
+        },
+        history_config,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HashtagConfig;
+
+    #[test]
+    fn rewrite_history_file_test() {
+        let dir: PathBuf =
+            std::env::temp_dir().join(format!("history-tidy-rewrite-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let history_path: PathBuf = dir.join("history");
+        write(&history_path, "ls #file\n#1690000000\npwd #here\n").unwrap();
+
+        let normalizer: HashtagNormalizer = HashtagNormalizer::default();
+        let changed: bool = rewrite_history_file(&history_path, |history: &str| {
+            replace_hashtag(history, "#file", "#fs", &normalizer)
+        })
+        .unwrap();
+        assert!(changed);
+        assert_eq!(
+            read_to_string(&history_path).unwrap(),
+            "ls #fs\n#1690000000\npwd #here\n"
+        );
+        assert!(!dir.join("history.tmp").exists());
+
+        // lines a shell appends after the file was read are kept
+        replace_history_file(&history_path, 7, "cd #dir\n".to_owned()).unwrap();
+        assert_eq!(
+            read_to_string(&history_path).unwrap(),
+            "cd #dir\n#1690000000\npwd #here\n"
+        );
+
+        let unchanged: bool =
+            rewrite_history_file(&history_path, |history: &str| history.to_owned()).unwrap();
+        assert!(!unchanged);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn get_copied_records_test() {
+        let normalizer: HashtagNormalizer = HashtagNormalizer::default();
+        let history_file_content: &str =
+            "#1690000000\t0\tlaptop\t42-1\t/src\nmake #build\n: 1690000100:0;vim #edit\n";
+        // $HISTFILE, which has the first make run too
+        let source_records: Vec<HistoryRecord> = parse_history(
+            "#1690000000\nmake #build\n#1690000050\nmake #build\nvim #edit\nls #build\nls #build\npwd\n",
+        );
+        let copied_records: Vec<HistoryRecord> =
+            get_copied_records(history_file_content, source_records, |history: &str| {
+                replace_hashtag(history, "#build", "#make", &normalizer)
+            });
+        assert_eq!(
+            copied_records
+                .iter()
+                .map(|history_record: &HistoryRecord| history_record.entry.command.as_str())
+                .collect::<Vec<&str>>(),
+            ["make #make", "ls #make", "ls #make"]
+        );
+        assert_eq!(
+            copied_records[0].to_history_string(),
+            ": 1690000050:0;make #make"
+        );
+    }
+
+    #[test]
+    fn replace_hashtag_test() {
+        let normalizer: HashtagNormalizer = HashtagNormalizer::default();
+        assert_eq!(
+            replace_hashtag(
+                "make deploy #deploy #prod",
+                "#deploy",
+                "#release",
+                &normalizer
+            ),
+            "make deploy #release #prod"
+        );
+        assert_eq!(
+            replace_hashtag("ls #deploy #deployment", "#deploy", "#release", &normalizer),
+            "ls #release #deployment"
+        );
+        assert_eq!(
+            replace_hashtag("echo ほげ #タグ", "#タグ", "#tag", &normalizer),
+            "echo ほげ #tag"
+        );
+        assert_eq!(
+            replace_hashtag("ls -a", "#deploy", "#release", &normalizer),
+            "ls -a"
+        );
+        // only the comment is rewritten, not a quoted "#deploy"
+        assert_eq!(
+            replace_hashtag(
+                "echo \"#deploy\" #deploy",
+                "#deploy",
+                "#release",
+                &normalizer
+            ),
+            "echo \"#deploy\" #release"
+        );
+
+        let mut config: HashtagConfig = HashtagConfig::default();
+        config
+            .aliases
+            .insert("#dkr".to_owned(), "#docker".to_owned());
+        let normalizer: HashtagNormalizer = HashtagNormalizer::new(&config);
+        assert_eq!(
+            replace_hashtag(
+                "docker ps #Docker #dkr #dkr2",
+                "#docker",
+                "#container",
+                &normalizer
+            ),
+            "docker ps #container #container #dkr2"
+        );
+    }
+
+    #[test]
+    fn remove_hashtag_test() {
+        let normalizer: HashtagNormalizer = HashtagNormalizer::default();
+        assert_eq!(
+            remove_hashtag("cd ~ #hoge #fuga", "#hoge", &normalizer),
+            "cd ~ #fuga"
+        );
+        assert_eq!(
+            remove_hashtag("cd ~ #hoge #fuga", "#fuga", &normalizer),
+            "cd ~ #hoge"
+        );
+        assert_eq!(remove_hashtag("#hoge pwd", "#hoge", &normalizer), "# pwd");
+        assert_eq!(
+            remove_hashtag("make #note rm -rf build", "#note", &normalizer),
+            "make # rm -rf build"
+        );
+        assert_eq!(
+            remove_hashtag("make # run it #build", "#build", &normalizer),
+            "make # run it"
+        );
+        assert_eq!(
+            remove_hashtag("pwd #hogehoge", "#hoge", &normalizer),
+            "pwd #hogehoge"
+        );
+    }
+
+    #[test]
+    fn strip_hashtags_test() {
+        assert_eq!(
+            strip_hashtags("kubectl get pods #k8s #debug"),
+            "kubectl get pods"
+        );
+        assert_eq!(
+            strip_hashtags("kubectl #k8s get pods #debug"),
+            "kubectl # get pods"
+        );
+        assert_eq!(
+            strip_hashtags("make #note rm -rf build"),
+            "make # rm -rf build"
+        );
+        assert_eq!(
+            strip_hashtags("git push #wip don't force"),
+            "git push # don't force"
+        );
+        assert_eq!(strip_hashtags("make # run it #build"), "make # run it");
+        assert_eq!(
+            strip_hashtags("echo \"#notatag\" #env=prod"),
+            "echo \"#notatag\""
+        );
+        assert_eq!(strip_hashtags("ls -a"), "ls -a");
+    }
+
+    #[test]
+    fn parse_hashtag_input_test() {
+        assert_eq!(
+            parse_hashtag_input("#k8s #debug"),
+            Some(vec!["#k8s".to_owned(), "#debug".to_owned()])
+        );
+        assert_eq!(parse_hashtag_input(" #k8s "), Some(vec!["#k8s".to_owned()]));
+        assert_eq!(parse_hashtag_input("k8s"), None);
+        assert_eq!(parse_hashtag_input("#k8s debug"), None);
+        assert_eq!(parse_hashtag_input("#k8s!"), None);
+        assert_eq!(parse_hashtag_input(""), None);
+    }
+
+    #[test]
+    fn append_hashtags_test() {
+        let normalizer: HashtagNormalizer = HashtagNormalizer::default();
+        let hashtag_names: Vec<String> = vec!["#k8s".to_owned(), "#debug".to_owned()];
+        assert_eq!(
+            append_hashtags("kubectl get pods", &hashtag_names, &normalizer),
+            "kubectl get pods #k8s #debug"
+        );
+        assert_eq!(
+            append_hashtags("kubectl get pods #K8s", &hashtag_names, &normalizer),
+            "kubectl get pods #K8s #debug"
+        );
+    }
+}
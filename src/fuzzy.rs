@@ -0,0 +1,132 @@
+const SCORE_MATCH: i64 = 16;
+const BONUS_BOUNDARY: i64 = 8;
+const BONUS_CONSECUTIVE: i64 = 8;
+const PENALTY_GAP_START: i64 = 3;
+const PENALTY_GAP_EXTENSION: i64 = 1;
+
+#[derive(Eq, PartialEq, Debug)]
+pub struct FuzzyMatch {
+    pub score: i64,
+    // char indices of the matched characters in the text
+    pub indices: Vec<usize>,
+}
+
+fn is_same_char(pattern_char: char, text_char: char, case_sensitive: bool) -> bool {
+    if case_sensitive {
+        return pattern_char == text_char;
+    }
+    return text_char.to_lowercase().eq(pattern_char.to_lowercase());
+}
+
+fn is_boundary(chars: &[char], index: usize) -> bool {
+    if index == 0 {
+        return true;
+    }
+    let prev: char = chars[index - 1];
+    let current: char = chars[index];
+    return !prev.is_alphanumeric() || (prev.is_lowercase() && current.is_uppercase());
+}
+
+// Finds the shortest window that contains the pattern as a subsequence, the same way as fzf's
+// v1 algorithm: scan forward for the first complete match, then backward for its tightest start.
+pub fn fuzzy_match(pattern: &str, text: &str) -> Option<FuzzyMatch> {
+    let pattern_chars: Vec<char> = pattern.chars().collect();
+    if pattern_chars.len() == 0 {
+        return Some(FuzzyMatch {
+            score: 0,
+            indices: vec![],
+        });
+    }
+    let text_chars: Vec<char> = text.chars().collect();
+    // smart case: only an uppercase letter in the pattern makes the search case sensitive
+    let case_sensitive: bool = pattern_chars.iter().any(|c: &char| c.is_uppercase());
+
+    let mut pattern_index: usize = 0;
+    let mut end: Option<usize> = None;
+    for (i, text_char) in text_chars.iter().enumerate() {
+        if is_same_char(pattern_chars[pattern_index], *text_char, case_sensitive) {
+            pattern_index += 1;
+            if pattern_index == pattern_chars.len() {
+                end = Some(i);
+                break;
+            }
+        }
+    }
+    let end: usize = end?;
+
+    let mut start: usize = end;
+    let mut pattern_index: usize = pattern_chars.len();
+    for i in (0..=end).rev() {
+        if is_same_char(
+            pattern_chars[pattern_index - 1],
+            text_chars[i],
+            case_sensitive,
+        ) {
+            pattern_index -= 1;
+            if pattern_index == 0 {
+                start = i;
+                break;
+            }
+        }
+    }
+
+    let mut indices: Vec<usize> = vec![];
+    let mut score: i64 = 0;
+    let mut pattern_index: usize = 0;
+    let mut in_gap: bool = false;
+    for i in start..=end {
+        if pattern_index < pattern_chars.len()
+            && is_same_char(pattern_chars[pattern_index], text_chars[i], case_sensitive)
+        {
+            score += SCORE_MATCH;
+            if is_boundary(&text_chars, i) {
+                score += BONUS_BOUNDARY;
+            }
+            if indices.last() == Some(&(i.wrapping_sub(1))) {
+                score += BONUS_CONSECUTIVE;
+            }
+            indices.push(i);
+            pattern_index += 1;
+            in_gap = false;
+        } else {
+            score -= if in_gap {
+                PENALTY_GAP_EXTENSION
+            } else {
+                PENALTY_GAP_START
+            };
+            in_gap = true;
+        }
+    }
+
+    return Some(FuzzyMatch { score, indices });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fuzzy_match_test() {
+        assert_eq!(
+            fuzzy_match("kgp", "kubectl get pods").unwrap().indices,
+            vec![0, 8, 12]
+        );
+        assert_eq!(
+            fuzzy_match("dpl", "#deploy").unwrap().indices,
+            vec![1, 3, 4]
+        );
+        assert_eq!(fuzzy_match("Dep", "#deploy"), None);
+        assert_eq!(fuzzy_match("xyz", "kubectl get pods"), None);
+        assert_eq!(fuzzy_match("", "ls").unwrap().indices, Vec::<usize>::new());
+    }
+
+    #[test]
+    fn fuzzy_match_score_test() {
+        let consecutive: i64 = fuzzy_match("get", "kubectl get pods").unwrap().score;
+        let scattered: i64 = fuzzy_match("get", "git reset").unwrap().score;
+        assert!(consecutive > scattered);
+
+        // the tightest window is preferred over the first one
+        assert_eq!(fuzzy_match("ls", "l -a; ls").unwrap().indices, vec![6, 7]);
+    }
+}
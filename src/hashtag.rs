@@ -36,6 +36,7 @@ THE SOFTWARE.
 //!     parser.next().unwrap(),
 //!     Hashtag {
 //!         text: Cow::from("rust"),
+//!         value: None,
 //!         start: 0,
 //!         end: 4,
 //!     }
@@ -45,6 +46,7 @@ THE SOFTWARE.
 //!     parser.next().unwrap(),
 //!     Hashtag {
 //!         text: Cow::from("awesome"),
+//!         value: None,
 //!         start: 9,
 //!         end: 16,
 //!     }
@@ -91,6 +93,12 @@ pub struct Hashtag<'a> {
     /// If hashtag is `"#rust"` the text will be `"rust"`.
     pub text: Cow<'a, str>,
 
+    /// The value of a key=value hashtag.
+    ///
+    /// If hashtag is `"#env=prod"` the text will be `"env"` and the value will be `"prod"`. Values
+    /// may contain characters like `-` and `.` that end the text, so `"#ticket=OPS-123"` works.
+    pub value: Option<Cow<'a, str>>,
+
     /// The starting index of the hashtag.
     ///
     /// This includes the `#` character. This makes it easier to highlight the hashtags later. If
@@ -107,6 +115,9 @@ impl<'a> fmt::Display for Hashtag<'a> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "#")?;
         write!(f, "{}", self.text)?;
+        if let Some(value) = &self.value {
+            write!(f, "={}", value)?;
+        }
         Ok(())
     }
 }
@@ -125,6 +136,8 @@ pub struct HashtagParser<'a> {
     whole_string: &'a str,
     state: IterState<'a>,
     done: bool,
+    // char index of `whole_string` in the text given to the constructor
+    offset: usize,
 }
 
 impl<'a> HashtagParser<'a> {
@@ -134,8 +147,82 @@ impl<'a> HashtagParser<'a> {
             whole_string: text,
             done: false,
             state: IterState::Init,
+            offset: 0,
+        }
+    }
+
+    /// Create a new `HashtagParser` that treats the given string as a shell command, and only
+    /// finds hashtags in its trailing comment.
+    ///
+    /// The comment starts at the first `#` that begins a word outside of quotes and `$(...)` or
+    /// `${...}`, the same as in bash. So `echo "#a"`, `curl http://x/#a` and `${#a[@]}` have no
+    /// hashtags, while `ls #a` does.
+    pub fn new_shell(text: &'a str) -> Self {
+        match find_shell_comment(text) {
+            Some((byte_index, char_index)) => Self {
+                whole_string: &text[byte_index..],
+                done: false,
+                state: IterState::Init,
+                offset: char_index,
+            },
+            None => Self {
+                whole_string: "",
+                done: true,
+                state: IterState::Init,
+                offset: 0,
+            },
+        }
+    }
+}
+
+#[inline]
+fn is_shell_word_boundary(c: char) -> bool {
+    c.is_whitespace() || matches!(c, ';' | '&' | '|' | '(' | ')' | '<' | '>')
+}
+
+/// Returns the byte and char index of the `#` starting the comment of a shell command.
+pub fn find_shell_comment(text: &str) -> Option<(usize, usize)> {
+    let mut quote: Option<char> = None;
+    // how deep we are in `$(...)` and `${...}`
+    let mut depth: usize = 0;
+    let mut prev: Option<char> = None;
+    let mut chars = text.char_indices().enumerate().peekable();
+    while let Some((char_index, (byte_index, c))) = chars.next() {
+        match quote {
+            Some('\'') => {
+                if c == '\'' {
+                    quote = None;
+                }
+            }
+            Some(q) => {
+                if c == '\\' {
+                    chars.next();
+                } else if c == q {
+                    quote = None;
+                }
+            }
+            None => match c {
+                '\\' => {
+                    chars.next();
+                }
+                '\'' | '"' | '`' => quote = Some(c),
+                '$' => {
+                    if let Some((_, (_, '(' | '{'))) = chars.peek() {
+                        depth += 1;
+                        chars.next();
+                    }
+                }
+                '(' if depth > 0 => depth += 1,
+                ')' | '}' if depth > 0 => depth -= 1,
+                '#' if depth == 0 && prev.map(is_shell_word_boundary) != Some(false) => {
+                    return Some((byte_index, char_index));
+                }
+                _ => {}
+            },
         }
+        prev = Some(c);
     }
+    None
 }
 
 #[derive(Debug)]
@@ -151,6 +238,18 @@ impl<'a> Iterator for HashtagParser<'a> {
     type Item = Hashtag<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        let offset = self.offset;
+        self.next_in_whole_string().map(|hashtag| Hashtag {
+            start: hashtag.start + offset,
+            end: hashtag.end + offset,
+            ..hashtag
+        })
+    }
+}
+
+impl<'a> HashtagParser<'a> {
+    #[allow(clippy::needless_borrow)]
+    fn next_in_whole_string(&mut self) -> Option<Hashtag<'a>> {
         loop {
             if self.done {
                 return None;
@@ -195,7 +294,30 @@ impl<'a> Iterator for HashtagParser<'a> {
                                 if stm.parsing_hashtag() {
                                     let mut hashtag = None;
 
-                                    if c.is_end_of_hashtag() {
+                                    // `#aws/s3`, a separator between two parts of a tag
+                                    let is_separator = c.is_hashtag_separator()
+                                        && stm.consumed_anything()
+                                        && !tokens.peek().map(|(_, tok)| tok).is_end_of_hashtag()
+                                        && !tokens.peek().map(|(_, tok)| tok).is_hashtag_token();
+
+                                    // `#env=prod`, the `=` between a key and its value
+                                    let is_value_start = c == '='
+                                        && stm.consumed_anything()
+                                        && !stm.parsing_value()
+                                        && !tokens
+                                            .peek()
+                                            .map(|(_, tok)| tok)
+                                            .is_end_of_hashtag_value();
+
+                                    if stm.parsing_value() {
+                                        if c.is_end_of_hashtag_value() {
+                                            hashtag = stm.hashtag_finishes_at(i - 2);
+                                        } else {
+                                            stm.consume_value_char(c);
+                                        }
+                                    } else if is_value_start {
+                                        stm.value_starts_at(i - 1);
+                                    } else if c.is_end_of_hashtag() && !is_separator {
                                         hashtag = stm.hashtag_finishes_at(i - 2);
                                     } else {
                                         stm.consume_char(c);
@@ -241,6 +363,8 @@ impl<'a> std::iter::FusedIterator for HashtagParser<'a> {}
 struct ParsingStateMachine<'a> {
     consumed_anything: bool,
     hashtag_buffer: String,
+    value_buffer: String,
+    value_start_index: Option<usize>,
     hashtag_start_index: usize,
     parsing_hashtag: bool,
     is_ascii: bool,
@@ -254,6 +378,8 @@ impl<'a> ParsingStateMachine<'a> {
             parsing_hashtag: Self::default_parse_hashtag(),
             hashtag_start_index: Self::default_hashtag_start_index(),
             hashtag_buffer: String::new(),
+            value_buffer: String::new(),
+            value_start_index: None,
             consumed_anything: Self::default_consumed_anything(),
             is_ascii: text.is_ascii(),
             whole_string: text,
@@ -280,22 +406,48 @@ impl<'a> ParsingStateMachine<'a> {
         self.parsing_hashtag
     }
 
+    #[inline]
+    fn consumed_anything(&self) -> bool {
+        self.consumed_anything
+    }
+
+    #[inline]
+    fn parsing_value(&self) -> bool {
+        self.value_start_index.is_some()
+    }
+
     #[inline]
     fn hashtag_token_seen_at(&mut self, idx: usize) {
         self.hashtag_start_index = idx;
+        self.value_start_index = None;
+        self.value_buffer.clear();
+    }
+
+    #[inline]
+    fn value_starts_at(&mut self, idx: usize) {
+        self.value_start_index = Some(idx);
     }
 
     #[inline]
     fn hashtag_finishes_at(&mut self, idx: usize) -> Option<Hashtag<'a>> {
         let hashtag = if self.consumed_anything {
+            let text_end = self.value_start_index.unwrap_or(idx + 1);
             let text = if self.is_ascii {
-                Cow::Borrowed(&self.whole_string[self.hashtag_start_index + 1..idx + 1])
+                Cow::Borrowed(&self.whole_string[self.hashtag_start_index + 1..text_end])
             } else {
                 Cow::Owned(self.hashtag_buffer.clone())
             };
+            let value = self.value_start_index.map(|value_start_index| {
+                if self.is_ascii {
+                    Cow::Borrowed(&self.whole_string[value_start_index + 1..idx + 1])
+                } else {
+                    Cow::Owned(self.value_buffer.clone())
+                }
+            });
 
             Some(Hashtag {
                 text,
+                value,
                 start: self.hashtag_start_index,
                 end: idx,
             })
@@ -311,6 +463,8 @@ impl<'a> ParsingStateMachine<'a> {
         self.parsing_hashtag = Self::default_parse_hashtag();
         self.hashtag_start_index = Self::default_hashtag_start_index();
         self.hashtag_buffer.clear();
+        self.value_buffer.clear();
+        self.value_start_index = None;
         self.consumed_anything = Self::default_consumed_anything();
     }
 
@@ -326,6 +480,13 @@ impl<'a> ParsingStateMachine<'a> {
         }
         self.consumed_anything = true;
     }
+
+    #[inline]
+    fn consume_value_char(&mut self, c: char) {
+        if !self.is_ascii {
+            self.value_buffer.push(c);
+        }
+    }
 }
 #[derive(Eq, PartialEq, Debug)]
 enum Token {
@@ -347,6 +508,7 @@ impl IsHashtagToken for Token {
     }
 }
 
+#[allow(clippy::needless_lifetimes)]
 impl<'a, T> IsHashtagToken for Option<&'a T>
 where
     T: IsHashtagToken,
@@ -388,6 +550,20 @@ trait IsEndOfHashtag {
     fn is_end_of_hashtag(&self) -> bool;
 }
 
+/// The characters that separate the levels of a hierarchical hashtag like `#aws/s3`.
+pub const HASHTAG_SEPARATORS: [char; 2] = ['/', ':'];
+
+trait IsHashtagSeparator {
+    fn is_hashtag_separator(&self) -> bool;
+}
+
+impl IsHashtagSeparator for char {
+    #[inline]
+    fn is_hashtag_separator(&self) -> bool {
+        HASHTAG_SEPARATORS.contains(self)
+    }
+}
+
 impl IsEndOfHashtag for char {
     #[inline]
     fn is_end_of_hashtag(&self) -> bool {
@@ -402,6 +578,58 @@ impl IsEndOfHashtag for char {
     }
 }
 
+trait IsEndOfHashtagValue {
+    fn is_end_of_hashtag_value(&self) -> bool;
+}
+
+impl IsEndOfHashtagValue for char {
+    #[inline]
+    fn is_end_of_hashtag_value(&self) -> bool {
+        matches!(
+            self,
+            '\'' | '"'
+                | '`'
+                | '\\'
+                | ','
+                | ';'
+                | '('
+                | ')'
+                | '['
+                | ']'
+                | '{'
+                | '}'
+                | '<'
+                | '>'
+                | '|'
+                | '&'
+                | '!'
+                | '='
+                | '#'
+        ) || self.is_whitespace()
+    }
+}
+
+impl IsEndOfHashtagValue for Token {
+    #[inline]
+    fn is_end_of_hashtag_value(&self) -> bool {
+        match self {
+            Token::Char(c) => c.is_end_of_hashtag_value(),
+            Token::StartOfString => false,
+            _ => true,
+        }
+    }
+}
+
+impl<T> IsEndOfHashtagValue for Option<&T>
+where
+    T: IsEndOfHashtagValue,
+{
+    #[inline]
+    fn is_end_of_hashtag_value(&self) -> bool {
+        self.map(|x| x.is_end_of_hashtag_value()).unwrap_or(true)
+    }
+}
+
 impl IsEndOfHashtag for Token {
     #[inline]
     fn is_end_of_hashtag(&self) -> bool {
@@ -415,6 +643,7 @@ impl IsEndOfHashtag for Token {
     }
 }
 
+#[allow(clippy::needless_lifetimes)]
 impl<'a, T> IsEndOfHashtag for Option<&'a T>
 where
     T: IsEndOfHashtag,
@@ -457,4 +686,86 @@ mod tests {
             vec![Token::StartOfString, Token::Char('😀'), Token::EndOfString,]
         );
     }
+
+    fn shell_hashtags(text: &str) -> Vec<(String, usize, usize)> {
+        HashtagParser::new_shell(text)
+            .map(|hashtag| (hashtag.to_string(), hashtag.start, hashtag.end))
+            .collect()
+    }
+
+    #[test]
+    fn test_shell_comment_hashtags() {
+        assert_eq!(
+            shell_hashtags("ls -a #file #list"),
+            vec![("#file".to_owned(), 6, 10), ("#list".to_owned(), 12, 16)]
+        );
+        assert_eq!(
+            shell_hashtags("#deploy"),
+            vec![("#deploy".to_owned(), 0, 6)]
+        );
+        assert_eq!(
+            shell_hashtags("make # run it #build"),
+            vec![("#build".to_owned(), 14, 19)]
+        );
+        assert_eq!(
+            shell_hashtags("echo \"#notatag\" 'it''s #no' \\#no;#yes"),
+            vec![("#yes".to_owned(), 33, 36)]
+        );
+        assert_eq!(shell_hashtags("curl http://x/#frag"), vec![]);
+        assert_eq!(
+            shell_hashtags("echo ${#arr[@]} $(( 16#ff )) $(ls #no)"),
+            vec![]
+        );
+        assert_eq!(
+            shell_hashtags("echo ${#arr[@]} #count"),
+            vec![("#count".to_owned(), 16, 21)]
+        );
+        assert_eq!(
+            shell_hashtags("echo 😀 #émoji"),
+            vec![("#émoji".to_owned(), 7, 12)]
+        );
+    }
+
+    #[test]
+    fn test_hierarchical_hashtags() {
+        let hashtags: Vec<String> = HashtagParser::new("ls #aws/s3 #aws:ec2:prod #a/ #b// #/c")
+            .map(|hashtag| hashtag.to_string())
+            .collect();
+        assert_eq!(hashtags, vec!["#aws/s3", "#aws:ec2:prod", "#a", "#b"]);
+        assert_eq!(
+            shell_hashtags("curl http://x/ #aws/s3"),
+            vec![("#aws/s3".to_owned(), 15, 21)]
+        );
+    }
+
+    #[test]
+    fn test_key_value_hashtags() {
+        let hashtags: Vec<(String, Option<String>)> =
+            HashtagParser::new("#env=prod #ticket=OPS-123, #aws/region=eu.1 #a= #=b #é=ü #c")
+                .map(|hashtag| {
+                    (
+                        hashtag.text.into_owned(),
+                        hashtag.value.map(Cow::into_owned),
+                    )
+                })
+                .collect();
+        assert_eq!(
+            hashtags,
+            vec![
+                ("env".to_owned(), Some("prod".to_owned())),
+                ("ticket".to_owned(), Some("OPS-123".to_owned())),
+                ("aws/region".to_owned(), Some("eu.1".to_owned())),
+                ("a".to_owned(), None),
+                ("é".to_owned(), Some("ü".to_owned())),
+                ("c".to_owned(), None),
+            ]
+        );
+        assert_eq!(
+            shell_hashtags("kubectl apply #env=prod #k8s"),
+            vec![
+                ("#env=prod".to_owned(), 14, 22),
+                ("#k8s".to_owned(), 24, 27)
+            ]
+        );
+    }
 }
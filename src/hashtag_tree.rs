@@ -0,0 +1,222 @@
+use crate::hashtag::HASHTAG_SEPARATORS;
+use crate::parse_history::HistoryEntry;
+use crate::parse_history::ALL_HASHTAG;
+use linked_hash_map::LinkedHashMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+// "#aws/s3" -> "#aws", and "#env=prod" -> "#env" so values are grouped under their key
+pub fn get_parent_hashtag(hashtag_name: &str) -> Option<&str> {
+    // a value is never split, even if it contains a separator
+    if let Some(index) = hashtag_name.find('=') {
+        return Some(&hashtag_name[..index]);
+    }
+    let index: usize = hashtag_name.rfind(&HASHTAG_SEPARATORS[..])?;
+    return Some(&hashtag_name[..index]);
+}
+
+// "#aws/s3" -> ["#aws", "#aws/s3"]
+fn get_hashtag_path(hashtag_name: &str) -> Vec<&str> {
+    let mut path: Vec<&str> = vec![hashtag_name];
+    let mut hashtag_name: &str = hashtag_name;
+    while let Some(parent_name) = get_parent_hashtag(hashtag_name) {
+        path.push(parent_name);
+        hashtag_name = parent_name;
+    }
+    path.reverse();
+    return path;
+}
+
+pub fn get_hashtag_depth(hashtag_name: &str) -> usize {
+    return get_hashtag_path(hashtag_name).len() - 1;
+}
+
+// The hashtags and all their parents, each parent right before its children.
+pub fn get_hashtag_tree(hashtag_names: &[&str]) -> Vec<String> {
+    let mut tree: HashSet<&str> = HashSet::new();
+    for hashtag_name in hashtag_names {
+        tree.extend(get_hashtag_path(hashtag_name));
+    }
+
+    let mut tree: Vec<&str> = tree.into_iter().collect();
+    // comparing level by level keeps "#aws-cli" from landing between "#aws" and "#aws/s3"
+    tree.sort_by_cached_key(|hashtag_name: &&str| get_hashtag_path(hashtag_name));
+    return tree.into_iter().map(|name: &str| name.to_owned()).collect();
+}
+
+// The children of each parent in the hashtag tree, built once so the descendants of a hashtag
+// are found without going through every hashtag.
+pub fn get_child_hashtags(
+    history_map: &LinkedHashMap<String, Vec<HistoryEntry>>,
+) -> HashMap<String, HashSet<String>> {
+    let mut child_hashtags: HashMap<String, HashSet<String>> = HashMap::new();
+    for hashtag_name in history_map.keys() {
+        let path: Vec<&str> = get_hashtag_path(hashtag_name);
+        for pair in path.windows(2) {
+            child_hashtags
+                .entry(pair[0].to_owned())
+                .or_default()
+                .insert(pair[1].to_owned());
+        }
+    }
+    return child_hashtags;
+}
+
+// The groups of a hashtag and all its descendants that have commands.
+pub fn get_hashtag_groups<'a>(
+    history_map: &'a LinkedHashMap<String, Vec<HistoryEntry>>,
+    child_hashtags: &HashMap<String, HashSet<String>>,
+    hashtag_name: &str,
+) -> Vec<&'a Vec<HistoryEntry>> {
+    let mut groups: Vec<&Vec<HistoryEntry>> = vec![];
+    let mut hashtag_names: Vec<&str> = vec![hashtag_name];
+    while let Some(hashtag_name) = hashtag_names.pop() {
+        if let Some(entries) = history_map.get(hashtag_name) {
+            groups.push(entries);
+        }
+        if let Some(children) = child_hashtags.get(hashtag_name) {
+            hashtag_names.extend(children.iter().map(|child: &String| child.as_str()));
+        }
+    }
+    return groups;
+}
+
+fn get_group_commands<'a>(groups: &[&'a Vec<HistoryEntry>]) -> HashSet<&'a str> {
+    return groups
+        .iter()
+        .flat_map(|entries: &&Vec<HistoryEntry>| {
+            entries
+                .iter()
+                .map(|entry: &HistoryEntry| entry.command.as_str())
+        })
+        .collect();
+}
+
+// The commands of a hashtag and all its descendants, in the order of the ALL group.
+pub fn get_hashtag_commands(
+    history_map: &LinkedHashMap<String, Vec<HistoryEntry>>,
+    child_hashtags: &HashMap<String, HashSet<String>>,
+    hashtag_name: &str,
+) -> Vec<HistoryEntry> {
+    let groups: Vec<&Vec<HistoryEntry>> =
+        get_hashtag_groups(history_map, child_hashtags, hashtag_name);
+    if groups.len() == 1 {
+        return groups[0].to_owned();
+    }
+
+    let commands: HashSet<&str> = get_group_commands(&groups);
+    return match history_map.get(ALL_HASHTAG) {
+        Some(all) => all
+            .iter()
+            .filter(|entry: &&HistoryEntry| commands.contains(entry.command.as_str()))
+            .cloned()
+            .collect(),
+        None => vec![],
+    };
+}
+
+// How many commands get_hashtag_commands lists, without putting them in order.
+pub fn get_hashtag_command_count(
+    history_map: &LinkedHashMap<String, Vec<HistoryEntry>>,
+    child_hashtags: &HashMap<String, HashSet<String>>,
+    hashtag_name: &str,
+) -> usize {
+    let groups: Vec<&Vec<HistoryEntry>> =
+        get_hashtag_groups(history_map, child_hashtags, hashtag_name);
+    if groups.len() == 1 {
+        return groups[0].len();
+    }
+    return get_group_commands(&groups).len();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_history::CommandIndex;
+
+    #[test]
+    fn get_hashtag_tree_test() {
+        assert_eq!(get_parent_hashtag("#aws/ec2:prod"), Some("#aws/ec2"));
+        assert_eq!(get_parent_hashtag("#aws"), None);
+        assert_eq!(get_hashtag_depth("#aws/ec2:prod"), 2);
+        assert_eq!(get_parent_hashtag("#aws/url=http://x/y"), Some("#aws/url"));
+        assert_eq!(get_hashtag_depth("#aws/url=http://x/y"), 2);
+
+        assert_eq!(
+            get_hashtag_tree(&[
+                "#k8s",
+                "#aws/s3",
+                "#aws-cli",
+                "#aws:ec2/prod",
+                "#aws/iam",
+                "#env=prod",
+                "#env-x",
+                "#env=dev"
+            ]),
+            vec![
+                "#aws",
+                "#aws/iam",
+                "#aws/s3",
+                "#aws:ec2",
+                "#aws:ec2/prod",
+                "#aws-cli",
+                "#env",
+                "#env=dev",
+                "#env=prod",
+                "#env-x",
+                "#k8s"
+            ]
+        );
+    }
+
+    #[test]
+    fn get_hashtag_commands_test() {
+        let mut command_index: CommandIndex = CommandIndex::default();
+        command_index.extend(
+            [
+                "aws s3 ls #aws/s3",
+                "aws ec2 describe-instances #aws/ec2",
+                "aws configure #aws",
+                "aws s3 cp a b #aws/s3",
+                "kubectl get pods #k8s",
+            ]
+            .iter()
+            .map(|command: &&str| HistoryEntry::new(command))
+            .collect(),
+        );
+        let history_map = command_index.to_command_hashmap(None, &[]);
+        let child_hashtags: HashMap<String, HashSet<String>> = get_child_hashtags(&history_map);
+        let get_commands = |hashtag_name: &str| -> Vec<String> {
+            let commands: Vec<String> =
+                get_hashtag_commands(&history_map, &child_hashtags, hashtag_name)
+                    .into_iter()
+                    .map(|entry: HistoryEntry| entry.command)
+                    .collect();
+            assert_eq!(
+                get_hashtag_command_count(&history_map, &child_hashtags, hashtag_name),
+                commands.len()
+            );
+            return commands;
+        };
+
+        assert_eq!(
+            get_commands("#aws"),
+            vec![
+                "aws s3 ls #aws/s3",
+                "aws ec2 describe-instances #aws/ec2",
+                "aws configure #aws",
+                "aws s3 cp a b #aws/s3"
+            ]
+        );
+        assert_eq!(
+            get_commands("#aws/s3"),
+            vec!["aws s3 ls #aws/s3", "aws s3 cp a b #aws/s3"]
+        );
+        assert_eq!(get_commands("#gcp"), Vec::<String>::new());
+        assert_eq!(
+            child_hashtags["#aws"],
+            HashSet::from(["#aws/s3".to_owned(), "#aws/ec2".to_owned()])
+        );
+        assert!(!child_hashtags.contains_key("#k8s"));
+    }
+}
@@ -0,0 +1,69 @@
+use std::env::current_dir;
+use std::path::Path;
+use std::path::PathBuf;
+
+// The directory the "Here" group is built for: the enclosing git worktree, or else the current
+// directory alone.
+#[derive(Eq, PartialEq, Debug)]
+pub struct HereDir {
+    pub path: PathBuf,
+    pub is_worktree: bool,
+}
+
+impl HereDir {
+    pub fn new(dir: &Path) -> HereDir {
+        for ancestor in dir.ancestors() {
+            // `.git` is a file in linked worktrees and submodules
+            if ancestor.join(".git").exists() {
+                return HereDir {
+                    path: ancestor.to_path_buf(),
+                    is_worktree: true,
+                };
+            }
+        }
+        return HereDir {
+            path: dir.to_path_buf(),
+            is_worktree: false,
+        };
+    }
+
+    pub fn current() -> Option<HereDir> {
+        return current_dir().ok().map(|dir: PathBuf| HereDir::new(&dir));
+    }
+
+    pub fn contains(&self, cwd: &str) -> bool {
+        if self.is_worktree {
+            return Path::new(cwd).starts_with(&self.path);
+        }
+        return Path::new(cwd) == self.path;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::temp_dir;
+    use std::fs::create_dir_all;
+    use std::fs::remove_dir_all;
+
+    #[test]
+    fn here_dir_test() {
+        let root: PathBuf = temp_dir().join(format!("history-tidy-here-{}", std::process::id()));
+        let src: PathBuf = root.join("project").join("src");
+        create_dir_all(&src).unwrap();
+        create_dir_all(root.join("project").join(".git")).unwrap();
+
+        let here_dir: HereDir = HereDir::new(&src);
+        assert_eq!(here_dir.path, root.join("project"));
+        assert!(here_dir.contains(root.join("project").to_str().unwrap()));
+        assert!(here_dir.contains(src.to_str().unwrap()));
+        assert!(!here_dir.contains(root.join("project2").to_str().unwrap()));
+
+        let here_dir: HereDir = HereDir::new(&root);
+        assert!(!here_dir.is_worktree);
+        assert!(here_dir.contains(root.to_str().unwrap()));
+        assert!(!here_dir.contains(src.to_str().unwrap()));
+
+        remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,855 @@
+use crate::config::HistoryConfig;
+use crate::edit_history::strip_hashtags;
+use crate::normalize::HashtagNormalizer;
+use crate::parse_history::get_history_entries;
+use crate::parse_history::get_tidy_dir_path;
+use crate::parse_history::get_tidy_history;
+use crate::parse_history::parse_history;
+use crate::parse_history::CommandIdSet;
+use crate::parse_history::CommandIndex;
+use crate::parse_history::HistoryEntry;
+use crate::parse_history::HistoryRecord;
+use crate::parse_history::IndexedCommand;
+use linked_hash_map::LinkedHashMap;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs::canonicalize;
+use std::fs::read;
+use std::fs::remove_file;
+use std::fs::rename;
+use std::fs::write;
+use std::fs::File;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::path::Path;
+use std::path::PathBuf;
+
+const INDEX_MAGIC: &[u8; 4] = b"HTIX";
+// bump when the layout below changes, older indexes are then rebuilt
+const INDEX_VERSION: u32 = 6;
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+// bytes hashed at each end of the part of a history file the index covers
+const FINGERPRINT_WINDOW: u64 = 4096;
+
+pub fn get_index_file_path() -> Result<PathBuf, Error> {
+    return Ok(get_tidy_dir_path()?.join("index"));
+}
+
+// Loads the index of ~/.history-tidy/history and the other history sources, parsing only what
+// was appended to each since the last run.
+pub fn get_command_index(
+    history_config: &HistoryConfig,
+    normalizer: &HashtagNormalizer,
+) -> Result<CommandIndex, Error> {
+    let history_paths: Vec<PathBuf> = get_history_paths(history_config)?;
+    let index_file_path: PathBuf = get_index_file_path()?;
+    return load_command_index(&history_paths, &index_file_path, normalizer);
+}
+
+// The history file, then each other source once.
+pub fn get_history_paths(history_config: &HistoryConfig) -> Result<Vec<PathBuf>, Error> {
+    let history_file_path: PathBuf = history_config.get_file_path()?;
+    let mut history_paths: Vec<PathBuf> = vec![history_file_path.to_owned()];
+    for source_path in history_config.get_source_paths()? {
+        // the history file itself may be listed, like $HISTFILE when the init scripts set it to it
+        if !is_same_file(&source_path, &history_file_path) && !history_paths.contains(&source_path)
+        {
+            history_paths.push(source_path);
+        }
+    }
+    return Ok(history_paths);
+}
+
+fn is_same_file(path: &Path, other_path: &Path) -> bool {
+    match (canonicalize(path), canonicalize(other_path)) {
+        (Ok(path), Ok(other_path)) => return path == other_path,
+        _ => return path == other_path,
+    }
+}
+
+// Called after the history file is rewritten, which the fingerprint may not notice.
+pub fn invalidate_command_index() -> Result<(), Error> {
+    match remove_file(get_index_file_path()?) {
+        Ok(_) => {}
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    return Ok(());
+}
+
+// How far the index has read a history file.
+#[derive(Eq, PartialEq, Debug, Clone)]
+struct SourceState {
+    path: String,
+    offset: u64,
+    fingerprint: u64,
+}
+
+// The timestamped runs read so far, as the hash of the command without hashtags and the time,
+// with the history file that recorded them. Only kept when there is more than one file.
+type RunKeys = HashMap<(u64, i64), u32>;
+// the time of the run key that says the history file has the command
+const UNTIMED_RUN: i64 = i64::MIN;
+
+// The first path is ~/.history-tidy/history, the others are only read. A file that doesn't exist
+// yet is read as empty.
+pub fn load_command_index(
+    history_paths: &[PathBuf],
+    index_path: &Path,
+    normalizer: &HashtagNormalizer,
+) -> Result<CommandIndex, Error> {
+    let mut history_files: Vec<Option<File>> = vec![];
+    for history_path in history_paths {
+        match File::open(history_path) {
+            Ok(history_file) => history_files.push(Some(history_file)),
+            Err(e) if e.kind() == ErrorKind::NotFound => history_files.push(None),
+            Err(e) => return Err(e),
+        }
+    }
+
+    // a truncated or rewritten history file doesn't match the index, which is then rebuilt, and
+    // so does a changed list of files
+    let (mut command_index, mut sources, mut run_keys) = match read_index(index_path, normalizer) {
+        Some((command_index, sources, run_keys))
+            if sources.len() == history_paths.len()
+                && sources
+                    .iter()
+                    .zip(history_paths)
+                    .zip(&mut history_files)
+                    .all(|((source, history_path), history_file)| {
+                        source.path == history_path.to_string_lossy()
+                            && get_fingerprint(history_file, source.offset).ok()
+                                == Some(source.fingerprint)
+                    }) =>
+        {
+            (command_index, sources, run_keys)
+        }
+        _ => (
+            CommandIndex::new(normalizer.to_owned()),
+            history_paths
+                .iter()
+                .map(|history_path: &PathBuf| SourceState {
+                    path: history_path.to_string_lossy().into_owned(),
+                    offset: 0,
+                    fingerprint: get_fingerprint(&mut None, 0).unwrap(),
+                })
+                .collect(),
+            RunKeys::new(),
+        ),
+    };
+
+    let mut complete_histories: Vec<Vec<HistoryEntry>> = vec![];
+    let mut partial_histories: Vec<Vec<HistoryEntry>> = vec![];
+    for (source, history_file) in sources.iter_mut().zip(&mut history_files) {
+        let mut tail: Vec<u8> = vec![];
+        if let Some(history_file) = history_file {
+            history_file.seek(SeekFrom::Start(source.offset))?;
+            history_file.read_to_end(&mut tail)?;
+        }
+
+        // only whole records are cached, one still being written is read again next time
+        let mut complete_len: usize = match tail.iter().rposition(|b: &u8| *b == b'\n') {
+            Some(i) => i + 1,
+            None => 0,
+        };
+        let mut history_records: Vec<HistoryRecord> =
+            parse_history(&String::from_utf8_lossy(&tail[..complete_len]));
+        if let Some(history_record) = history_records.pop() {
+            if history_record.is_complete() {
+                history_records.push(history_record);
+            } else {
+                let line_count: usize =
+                    history_record.to_history_string().matches('\n').count() + 1;
+                complete_len = get_line_start(&tail[..complete_len], line_count);
+            }
+        }
+        complete_histories.push(get_history_entries(history_records));
+        partial_histories.push(get_tidy_history(&String::from_utf8_lossy(
+            &tail[complete_len..],
+        )));
+        if complete_len > 0 {
+            source.offset += complete_len as u64;
+            source.fingerprint = get_fingerprint(history_file, source.offset)?;
+        }
+    }
+
+    let is_changed: bool = complete_histories
+        .iter()
+        .any(|history: &Vec<HistoryEntry>| history.len() > 0);
+    command_index.extend(merge_histories(complete_histories, &mut run_keys));
+    if is_changed {
+        // the index only saves time, so failing to write it is not an error
+        let _ = write_index(index_path, &command_index, &sources, &run_keys);
+    }
+    command_index.extend(merge_histories(partial_histories, &mut run_keys));
+    return Ok(command_index);
+}
+
+// Where the last `line_count` lines of `bytes`, which ends with a new line, start.
+fn get_line_start(bytes: &[u8], line_count: usize) -> usize {
+    let mut newline_count: usize = 0;
+    for (i, b) in bytes.iter().enumerate().rev() {
+        if *b == b'\n' {
+            newline_count += 1;
+            if newline_count == line_count + 1 {
+                return i + 1;
+            }
+        }
+    }
+    return 0;
+}
+
+// Interleaves the new entries of each history file by time. An entry without a timestamp keeps
+// its place before the next one with a timestamp in its file, or after the others when its file
+// has none. A run that two files recorded, like a command in both ~/.history-tidy/history and
+// $HISTFILE, is kept only once. An untimed run can't be told apart from the others of its command,
+// so it is dropped from another source when the history file has the command.
+fn merge_histories(histories: Vec<Vec<HistoryEntry>>, run_keys: &mut RunKeys) -> Vec<HistoryEntry> {
+    if histories.len() == 1 {
+        return histories.into_iter().next().unwrap();
+    }
+
+    let mut runs: Vec<(i64, usize, HistoryEntry)> = vec![];
+    for (file_index, history) in histories.into_iter().enumerate() {
+        let mut next_timestamp: i64 = i64::MAX;
+        let mut file_runs: Vec<(i64, usize, HistoryEntry)> = vec![];
+        for entry in history.into_iter().rev() {
+            if let Some(timestamp) = entry.timestamp {
+                next_timestamp = timestamp;
+            }
+            file_runs.push((next_timestamp, file_index, entry));
+        }
+        runs.extend(file_runs.into_iter().rev());
+    }
+    // stable, so each file keeps its order, and the history file, which has the most metadata,
+    // comes first among runs at the same time, after the untimed runs that come before them
+    runs.sort_by_key(|(timestamp, file_index, entry)| {
+        (*timestamp, entry.timestamp.is_some(), *file_index)
+    });
+
+    for (_, file_index, entry) in &runs {
+        if *file_index == 0 {
+            run_keys.insert((get_run_hash(entry), UNTIMED_RUN), 0);
+        }
+    }
+
+    let mut merged: Vec<HistoryEntry> = vec![];
+    for (_, file_index, entry) in runs {
+        let command_hash: u64 = get_run_hash(&entry);
+        match entry.timestamp {
+            Some(timestamp) => match run_keys.get(&(command_hash, timestamp)) {
+                Some(other_index) if *other_index != file_index as u32 => continue,
+                Some(_) => {}
+                None => {
+                    run_keys.insert((command_hash, timestamp), file_index as u32);
+                }
+            },
+            None if file_index > 0 && run_keys.contains_key(&(command_hash, UNTIMED_RUN)) => {
+                continue
+            }
+            None => {}
+        }
+        merged.push(entry);
+    }
+    return merged;
+}
+
+fn get_run_hash(entry: &HistoryEntry) -> u64 {
+    return hash_bytes(FNV_OFFSET_BASIS, strip_hashtags(&entry.command).as_bytes());
+}
+
+// FNV-1a, which unlike std's hasher is the same on every build
+fn hash_bytes(hash: u64, bytes: &[u8]) -> u64 {
+    let mut hash: u64 = hash;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    return hash;
+}
+
+// A history file that doesn't exist only matches an offset of 0.
+fn get_fingerprint(history_file: &mut Option<File>, offset: u64) -> Result<u64, Error> {
+    let history_file: &mut File = match history_file {
+        Some(history_file) => history_file,
+        None if offset == 0 => return Ok(FNV_OFFSET_BASIS),
+        None => return Err(Error::new(ErrorKind::NotFound, "history file is gone")),
+    };
+    if offset > history_file.metadata()?.len() {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "history file is truncated",
+        ));
+    }
+    let window: u64 = offset.min(FINGERPRINT_WINDOW);
+    let mut hash: u64 = FNV_OFFSET_BASIS;
+    for start in [0, offset - window] {
+        let mut bytes: Vec<u8> = vec![0; window as usize];
+        history_file.seek(SeekFrom::Start(start))?;
+        history_file.read_exact(&mut bytes)?;
+        hash = hash_bytes(hash, &bytes);
+    }
+    return Ok(hash);
+}
+
+fn read_index(
+    index_path: &Path,
+    normalizer: &HashtagNormalizer,
+) -> Option<(CommandIndex, Vec<SourceState>, RunKeys)> {
+    let bytes: Vec<u8> = read(index_path).ok()?;
+    let mut reader: IndexReader = IndexReader {
+        bytes: &bytes,
+        position: 0,
+    };
+    if reader.read_bytes(INDEX_MAGIC.len()).ok()? != INDEX_MAGIC
+        || reader.read_u32().ok()? != INDEX_VERSION
+    {
+        return None;
+    }
+    return decode_index(&mut reader, normalizer).ok();
+}
+
+fn write_index(
+    index_path: &Path,
+    command_index: &CommandIndex,
+    sources: &[SourceState],
+    run_keys: &RunKeys,
+) -> Result<(), Error> {
+    let mut writer: IndexWriter = IndexWriter { bytes: vec![] };
+    writer.bytes.extend_from_slice(INDEX_MAGIC);
+    writer.write_u32(INDEX_VERSION);
+    encode_index(&mut writer, command_index, sources, run_keys);
+
+    // another shell may be reading the index, so it is replaced in one step
+    let tmp_path: PathBuf = index_path.with_extension("tmp");
+    write(&tmp_path, writer.bytes)?;
+    return rename(tmp_path, index_path);
+}
+
+struct IndexWriter {
+    bytes: Vec<u8>,
+}
+
+impl IndexWriter {
+    fn write_u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn write_str(&mut self, value: &str) {
+        self.write_u32(value.len() as u32);
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    fn write_optional_str(&mut self, value: &Option<String>) {
+        match value {
+            Some(value) => {
+                self.bytes.push(1);
+                self.write_str(value);
+            }
+            None => self.bytes.push(0),
+        }
+    }
+
+    fn write_optional_u64(&mut self, value: Option<u64>) {
+        match value {
+            Some(value) => {
+                self.bytes.push(1);
+                self.write_u64(value);
+            }
+            None => self.bytes.push(0),
+        }
+    }
+
+    fn write_ids(&mut self, ids: &CommandIdSet) {
+        self.write_u32(ids.len() as u32);
+        for id in ids.keys() {
+            self.write_u32(*id as u32);
+        }
+    }
+}
+
+struct IndexReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl IndexReader<'_> {
+    fn read_bytes(&mut self, len: usize) -> Result<&[u8], Error> {
+        if self.bytes.len() - self.position < len {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "index is truncated"));
+        }
+        self.position += len;
+        return Ok(&self.bytes[self.position - len..self.position]);
+    }
+
+    fn read_u8(&mut self) -> Result<u8, Error> {
+        return Ok(self.read_bytes(1)?[0]);
+    }
+
+    fn read_u32(&mut self) -> Result<u32, Error> {
+        let bytes: &[u8] = self.read_bytes(4)?;
+        return Ok(u32::from_le_bytes(bytes.try_into().unwrap()));
+    }
+
+    fn read_u64(&mut self) -> Result<u64, Error> {
+        let bytes: &[u8] = self.read_bytes(8)?;
+        return Ok(u64::from_le_bytes(bytes.try_into().unwrap()));
+    }
+
+    fn read_str(&mut self) -> Result<String, Error> {
+        let len: usize = self.read_u32()? as usize;
+        match String::from_utf8(self.read_bytes(len)?.to_vec()) {
+            Ok(value) => return Ok(value),
+            Err(e) => return Err(Error::new(ErrorKind::InvalidData, e)),
+        }
+    }
+
+    fn read_optional_str(&mut self) -> Result<Option<String>, Error> {
+        if self.read_u8()? == 0 {
+            return Ok(None);
+        }
+        return Ok(Some(self.read_str()?));
+    }
+
+    fn read_optional_u64(&mut self) -> Result<Option<u64>, Error> {
+        if self.read_u8()? == 0 {
+            return Ok(None);
+        }
+        return Ok(Some(self.read_u64()?));
+    }
+
+    fn read_ids(&mut self, command_count: usize) -> Result<CommandIdSet, Error> {
+        let mut ids: CommandIdSet = LinkedHashMap::new();
+        for _ in 0..self.read_u32()? {
+            let id: usize = self.read_u32()? as usize;
+            if id >= command_count {
+                return Err(Error::new(ErrorKind::InvalidData, "unknown command id"));
+            }
+            ids.insert(id, ());
+        }
+        return Ok(ids);
+    }
+}
+
+// Layout, all integers little endian and strings as a u32 length and UTF-8 bytes: the hashtag
+// settings, the path, offset and fingerprint of each history file, the run keys, run total, the
+// commands, the ALL ids, then each hashtag and its ids.
+fn encode_index(
+    writer: &mut IndexWriter,
+    command_index: &CommandIndex,
+    sources: &[SourceState],
+    run_keys: &RunKeys,
+) {
+    writer.write_str(&command_index.normalizer.get_cache_key());
+    writer.write_u32(sources.len() as u32);
+    for source in sources {
+        writer.write_str(&source.path);
+        writer.write_u64(source.offset);
+        writer.write_u64(source.fingerprint);
+    }
+    writer.write_u32(run_keys.len() as u32);
+    for ((command_hash, timestamp), file_index) in run_keys {
+        writer.write_u64(*command_hash);
+        writer.write_u64(*timestamp as u64);
+        writer.write_u32(*file_index);
+    }
+    writer.write_u64(command_index.run_total);
+
+    writer.write_u32(command_index.commands.len() as u32);
+    for command in &command_index.commands {
+        let entry: &HistoryEntry = &command.entry;
+        writer.write_str(&entry.command);
+        // signed values are stored as their bit pattern
+        writer.write_optional_u64(entry.timestamp.map(|timestamp: i64| timestamp as u64));
+        writer.write_optional_u64(
+            entry
+                .exit_status
+                .map(|exit_status: i32| exit_status as i64 as u64),
+        );
+        writer.write_optional_str(&entry.cwd);
+        writer.write_optional_str(&entry.hostname);
+        writer.write_optional_str(&entry.session_id);
+        writer.write_u64(entry.run_count as u64);
+
+        writer.write_u32(command.hashtags.len() as u32);
+        for hashtag in &command.hashtags {
+            writer.write_str(hashtag);
+        }
+        writer.write_u32(command.cwds.len() as u32);
+        for (cwd, last_run) in &command.cwds {
+            writer.write_str(cwd);
+            writer.write_u64(*last_run);
+        }
+    }
+
+    writer.write_ids(&command_index.all);
+    writer.write_u32(command_index.hashtag_ids.len() as u32);
+    for (hashtag, ids) in &command_index.hashtag_ids {
+        writer.write_str(hashtag);
+        writer.write_ids(ids);
+    }
+}
+
+fn decode_index(
+    reader: &mut IndexReader,
+    normalizer: &HashtagNormalizer,
+) -> Result<(CommandIndex, Vec<SourceState>, RunKeys), Error> {
+    // the hashtags were grouped under other names, so the whole history is parsed again
+    if reader.read_str()? != normalizer.get_cache_key() {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "hashtag settings changed",
+        ));
+    }
+    let mut sources: Vec<SourceState> = vec![];
+    for _ in 0..reader.read_u32()? {
+        sources.push(SourceState {
+            path: reader.read_str()?,
+            offset: reader.read_u64()?,
+            fingerprint: reader.read_u64()?,
+        });
+    }
+    let mut run_keys: RunKeys = RunKeys::new();
+    for _ in 0..reader.read_u32()? {
+        let command_hash: u64 = reader.read_u64()?;
+        let timestamp: i64 = reader.read_u64()? as i64;
+        run_keys.insert((command_hash, timestamp), reader.read_u32()?);
+    }
+    let run_total: u64 = reader.read_u64()?;
+
+    let mut commands: Vec<IndexedCommand> = vec![];
+    for _ in 0..reader.read_u32()? {
+        let entry: HistoryEntry = HistoryEntry {
+            command: reader.read_str()?,
+            timestamp: reader
+                .read_optional_u64()?
+                .map(|timestamp: u64| timestamp as i64),
+            exit_status: reader
+                .read_optional_u64()?
+                .map(|exit_status: u64| exit_status as i64 as i32),
+            cwd: reader.read_optional_str()?,
+            hostname: reader.read_optional_str()?,
+            session_id: reader.read_optional_str()?,
+            run_count: reader.read_u64()? as usize,
+        };
+        let mut hashtags: Vec<String> = vec![];
+        for _ in 0..reader.read_u32()? {
+            hashtags.push(reader.read_str()?);
+        }
+        let mut cwds: Vec<(String, u64)> = vec![];
+        for _ in 0..reader.read_u32()? {
+            cwds.push((reader.read_str()?, reader.read_u64()?));
+        }
+        commands.push(IndexedCommand {
+            entry,
+            hashtags,
+            cwds,
+        });
+    }
+
+    let all: CommandIdSet = reader.read_ids(commands.len())?;
+    let mut hashtag_ids: LinkedHashMap<String, CommandIdSet> = LinkedHashMap::new();
+    for _ in 0..reader.read_u32()? {
+        let hashtag: String = reader.read_str()?;
+        hashtag_ids.insert(hashtag, reader.read_ids(commands.len())?);
+    }
+
+    let command_index: CommandIndex =
+        CommandIndex::from_parts(commands, hashtag_ids, all, run_total, normalizer.to_owned());
+    return Ok((command_index, sources, run_keys));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HashtagConfig;
+    use std::env::temp_dir;
+    use std::fs::create_dir_all;
+    use std::fs::remove_dir_all;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+
+    fn get_full_index(history_file_content: &str) -> CommandIndex {
+        let mut command_index: CommandIndex = CommandIndex::default();
+        command_index.extend(get_tidy_history(history_file_content));
+        return command_index;
+    }
+
+    #[test]
+    fn index_round_trip_test() {
+        let command_index: CommandIndex = get_full_index(
+            "#1690000000\t1\tlaptop\t42-1\t/src/app
+make deploy #release #prod
+: 1690000100:0;ls -a
+make deploy #release #prod
+",
+        );
+        let sources: Vec<SourceState> = vec![SourceState {
+            path: "/home/user/.history-tidy/history".to_owned(),
+            offset: 120,
+            fingerprint: 7,
+        }];
+        let mut run_keys: RunKeys = RunKeys::new();
+        run_keys.insert((3, -1), 1);
+        let mut writer: IndexWriter = IndexWriter { bytes: vec![] };
+        encode_index(&mut writer, &command_index, &sources, &run_keys);
+        let mut reader: IndexReader = IndexReader {
+            bytes: &writer.bytes,
+            position: 0,
+        };
+        let normalizer: HashtagNormalizer = HashtagNormalizer::default();
+        assert_eq!(
+            decode_index(&mut reader, &normalizer).unwrap(),
+            (command_index, sources, run_keys)
+        );
+
+        // a truncated index is an error, not a panic
+        let mut reader: IndexReader = IndexReader {
+            bytes: &writer.bytes[..writer.bytes.len() - 3],
+            position: 0,
+        };
+        assert!(decode_index(&mut reader, &normalizer).is_err());
+    }
+
+    #[test]
+    fn load_command_index_test() {
+        let dir: PathBuf = temp_dir().join(format!("history-tidy-index-{}", std::process::id()));
+        create_dir_all(&dir).unwrap();
+        let history_path: PathBuf = dir.join("history");
+        let history_paths: Vec<PathBuf> = vec![history_path.to_owned()];
+        let index_path: PathBuf = dir.join("index");
+
+        let normalizer: HashtagNormalizer = HashtagNormalizer::default();
+        let first: &str = "ls -a #file\npwd #here\n";
+        write(&history_path, first).unwrap();
+        let command_index: CommandIndex =
+            load_command_index(&history_paths, &index_path, &normalizer).unwrap();
+        assert_eq!(command_index, get_full_index(first));
+        assert!(index_path.exists());
+
+        // appended lines are added to the cached index, a partial last line is not cached
+        let appended: &str = "#1690000000\t0\tlaptop\t42-1\t/tmp\nls -a #file\nmake";
+        OpenOptions::new()
+            .append(true)
+            .open(&history_path)
+            .unwrap()
+            .write_all(appended.as_bytes())
+            .unwrap();
+        let content: String = first.to_owned() + appended;
+        let command_index: CommandIndex =
+            load_command_index(&history_paths, &index_path, &normalizer).unwrap();
+        assert_eq!(command_index, get_full_index(&content));
+        let (cached_index, sources, _) = read_index(&index_path, &normalizer).unwrap();
+        let offset: u64 = sources[0].offset;
+        assert_eq!(offset, (content.len() - "make".len()) as u64);
+        assert_eq!(cached_index, get_full_index(&content[..offset as usize]));
+
+        // rewriting the file, even to the same length, rebuilds the index
+        let rewritten: String = content.replace("#file", "#fil2");
+        write(&history_path, &rewritten).unwrap();
+        let command_index: CommandIndex =
+            load_command_index(&history_paths, &index_path, &normalizer).unwrap();
+        assert_eq!(command_index, get_full_index(&rewritten));
+
+        let truncated: &str = "cd ~\n";
+        write(&history_path, truncated).unwrap();
+        let command_index: CommandIndex =
+            load_command_index(&history_paths, &index_path, &normalizer).unwrap();
+        assert_eq!(command_index, get_full_index(truncated));
+
+        // changing the hashtag settings rebuilds the index too
+        write(&history_path, "docker ps #Docker\n").unwrap();
+        load_command_index(&history_paths, &index_path, &normalizer).unwrap();
+        let mut config: HashtagConfig = HashtagConfig::default();
+        config
+            .aliases
+            .insert("#docker".to_owned(), "#container".to_owned());
+        let alias_normalizer: HashtagNormalizer = HashtagNormalizer::new(&config);
+        let command_index: CommandIndex =
+            load_command_index(&history_paths, &index_path, &alias_normalizer).unwrap();
+        assert_eq!(
+            command_index.hashtag_ids.keys().collect::<Vec<&String>>(),
+            vec!["#container"]
+        );
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn load_partial_record_test() {
+        let dir: PathBuf = temp_dir().join(format!("history-tidy-partial-{}", std::process::id()));
+        create_dir_all(&dir).unwrap();
+        let history_path: PathBuf = dir.join("history");
+        let history_paths: Vec<PathBuf> = vec![history_path.to_owned()];
+        let index_path: PathBuf = dir.join("index");
+        let normalizer: HashtagNormalizer = HashtagNormalizer::default();
+
+        write(&history_path, "ls\n").unwrap();
+        let mut content: String = "ls\n".to_owned();
+        // each record is written in two parts, the index stays at the end of the first record
+        // until the second part is there
+        for (first, second) in [
+            ("#1690000000\t0\tlaptop\t42-1\t/src\n", "make #build\n"),
+            (
+                ": 1690000100:0;for i in 1 2\\\n",
+                "do echo $i #loop; done\n",
+            ),
+        ] {
+            let offset: usize = content.len();
+            for part in [first, second] {
+                OpenOptions::new()
+                    .append(true)
+                    .open(&history_path)
+                    .unwrap()
+                    .write_all(part.as_bytes())
+                    .unwrap();
+                content.push_str(part);
+                let command_index: CommandIndex =
+                    load_command_index(&history_paths, &index_path, &normalizer).unwrap();
+                assert_eq!(command_index, get_full_index(&content));
+            }
+            let (_, sources, _) = read_index(&index_path, &normalizer).unwrap();
+            assert!(sources[0].offset as usize > offset);
+            assert_eq!(sources[0].offset as usize, content.len());
+        }
+
+        let command_index: CommandIndex =
+            load_command_index(&history_paths, &index_path, &normalizer).unwrap();
+        let make: &HistoryEntry = &command_index
+            .commands
+            .iter()
+            .find(|command: &&IndexedCommand| command.entry.command == "make #build")
+            .unwrap()
+            .entry;
+        assert_eq!(make.timestamp, Some(1690000000));
+        assert_eq!(make.cwd.as_deref(), Some("/src"));
+        assert!(command_index.hashtag_ids.contains_key("#loop"));
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn merge_histories_test() {
+        let get_entry = |command: &str, timestamp: Option<i64>| -> HistoryEntry {
+            return HistoryEntry {
+                timestamp,
+                ..HistoryEntry::new(command)
+            };
+        };
+        let histories: Vec<Vec<HistoryEntry>> = vec![
+            vec![
+                get_entry("make #build", Some(100)),
+                get_entry("ls", Some(200)),
+            ],
+            // zsh without EXTENDED_HISTORY
+            vec![
+                get_entry("vim", None),
+                get_entry("cd ~", Some(150)),
+                get_entry("make", None),
+                get_entry("pwd", None),
+            ],
+        ];
+        let mut run_keys: RunKeys = RunKeys::new();
+        // untimed runs stay before the next timed run of their file, and those the history file
+        // has are dropped
+        assert_eq!(
+            merge_histories(histories, &mut run_keys)
+                .iter()
+                .map(|entry: &HistoryEntry| entry.command.as_str())
+                .collect::<Vec<&str>>(),
+            ["make #build", "vim", "cd ~", "ls", "pwd"]
+        );
+
+        // also when the history file recorded the command in an earlier read
+        let histories: Vec<Vec<HistoryEntry>> = vec![vec![], vec![get_entry("ls", None)]];
+        assert_eq!(merge_histories(histories, &mut run_keys), []);
+    }
+
+    fn get_commands(command_index: &CommandIndex) -> Vec<(&str, usize)> {
+        return command_index
+            .all
+            .keys()
+            .map(|id: &usize| {
+                let entry: &HistoryEntry = &command_index.commands[*id].entry;
+                (entry.command.as_str(), entry.run_count)
+            })
+            .collect();
+    }
+
+    #[test]
+    fn load_history_sources_test() {
+        let dir: PathBuf = temp_dir().join(format!("history-tidy-sources-{}", std::process::id()));
+        create_dir_all(&dir).unwrap();
+        let history_path: PathBuf = dir.join("history");
+        let bash_history_path: PathBuf = dir.join("bash_history");
+        let history_paths: Vec<PathBuf> = vec![
+            history_path.to_owned(),
+            bash_history_path.to_owned(),
+            dir.join("zsh_history"),
+        ];
+        let index_path: PathBuf = dir.join("index");
+        let normalizer: HashtagNormalizer = HashtagNormalizer::default();
+
+        // the history file doesn't exist yet, the other sources are read anyway
+        write(
+            &bash_history_path,
+            "cd ~\n#1690000000\nmake #build\n#1690000100\nls\n",
+        )
+        .unwrap();
+        let command_index: CommandIndex =
+            load_command_index(&history_paths, &index_path, &normalizer).unwrap();
+        assert_eq!(
+            get_commands(&command_index),
+            [("cd ~", 1), ("make #build", 1), ("ls", 1)]
+        );
+
+        // runs recorded by both files are counted once
+        write(
+            &history_path,
+            "#1690000000\t0\tlaptop\t42-1\t/src\nmake #build\n#1690000050\t0\tlaptop\t42-1\t/src\npwd\n",
+        )
+        .unwrap();
+        let command_index: CommandIndex =
+            load_command_index(&history_paths, &index_path, &normalizer).unwrap();
+        assert_eq!(
+            get_commands(&command_index),
+            [("cd ~", 1), ("make #build", 1), ("ls", 1), ("pwd", 1)]
+        );
+
+        // a run appended later to one file, then to the other, is still counted once
+        let mut bash_history: File = OpenOptions::new()
+            .append(true)
+            .open(&bash_history_path)
+            .unwrap();
+        bash_history.write_all(b"#1690000200\npwd\n").unwrap();
+        load_command_index(&history_paths, &index_path, &normalizer).unwrap();
+        OpenOptions::new()
+            .append(true)
+            .open(&history_path)
+            .unwrap()
+            .write_all(b"#1690000200\t0\tlaptop\t42-1\t/src\npwd\n")
+            .unwrap();
+        let command_index: CommandIndex =
+            load_command_index(&history_paths, &index_path, &normalizer).unwrap();
+        assert_eq!(
+            get_commands(&command_index),
+            [("cd ~", 1), ("make #build", 1), ("ls", 1), ("pwd", 2)]
+        );
+
+        // and the cached index counts the runs as a full read does
+        remove_file(&index_path).unwrap();
+        assert_eq!(
+            get_commands(&load_command_index(&history_paths, &index_path, &normalizer).unwrap()),
+            get_commands(&command_index)
+        );
+
+        remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,635 @@
+use crossterm::event::KeyCode;
+use crossterm::event::KeyEvent;
+use crossterm::event::KeyModifiers;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+// What a key does in the Hashtag and Command Views. The search and edit modes take text instead.
+#[derive(Eq, PartialEq, Ord, PartialOrd, Debug, Clone, Copy)]
+pub enum Action {
+    Quit,
+    Search,
+    Edit,
+    Tag,
+    Delete,
+    Sort,
+    Query,
+    Merge,
+    MoveDown,
+    MoveUp,
+    PageDown,
+    PageUp,
+    MoveTop,
+    MoveBottom,
+    // unfolds a parent hashtag, or opens a hashtag
+    Open,
+    // folds a parent hashtag, selects the parent, or goes back to the Hashtag View
+    Back,
+    // opens a hashtag, or picks a command
+    Select,
+    // answers yes when asked, like before a hashtag is deleted
+    Confirm,
+}
+
+// As actions are named in config.toml.
+const ACTION_NAMES: &[(Action, &str)] = &[
+    (Action::Quit, "quit"),
+    (Action::Search, "search"),
+    (Action::Edit, "edit"),
+    (Action::Tag, "tag"),
+    (Action::Delete, "delete"),
+    (Action::Sort, "sort"),
+    (Action::Query, "query"),
+    (Action::Merge, "merge"),
+    (Action::MoveDown, "move_down"),
+    (Action::MoveUp, "move_up"),
+    (Action::PageDown, "page_down"),
+    (Action::PageUp, "page_up"),
+    (Action::MoveTop, "move_top"),
+    (Action::MoveBottom, "move_bottom"),
+    (Action::Open, "open"),
+    (Action::Back, "back"),
+    (Action::Select, "select"),
+    (Action::Confirm, "confirm"),
+];
+
+impl Action {
+    pub fn from_name(name: &str) -> Option<Action> {
+        return ACTION_NAMES
+            .iter()
+            .find(|(_, action_name)| *action_name == name)
+            .map(|(action, _)| *action);
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        return ACTION_NAMES
+            .iter()
+            .find(|(action, _)| action == self)
+            .map(|(_, action_name)| *action_name)
+            .unwrap();
+    }
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyPreset {
+    Default,
+    Vim,
+    Emacs,
+}
+
+// always quits, like it does everywhere else in the terminal
+const INTERRUPT_KEY: &str = "<C-c>";
+// without a key for these, history-tidy can't be left or used
+const REQUIRED_ACTIONS: &[Action] = &[Action::Quit, Action::Select];
+
+// The keys every preset starts from. The first key of an action is the one the help shows.
+const DEFAULT_BINDINGS: &[(Action, &[&str])] = &[
+    (Action::Quit, &["q", "<C-c>"]),
+    (Action::Search, &["/"]),
+    (Action::Edit, &["e"]),
+    (Action::Tag, &["t"]),
+    (Action::Delete, &["d"]),
+    (Action::Sort, &["s"]),
+    (Action::Query, &["f"]),
+    (Action::Merge, &["m"]),
+    (Action::MoveDown, &["<Down>"]),
+    (Action::MoveUp, &["<Up>"]),
+    (Action::PageDown, &["<PageDown>"]),
+    (Action::PageUp, &["<PageUp>"]),
+    (Action::MoveTop, &["<Home>"]),
+    (Action::MoveBottom, &["<End>"]),
+    (Action::Open, &["<Right>"]),
+    (Action::Back, &["<Left>"]),
+    (Action::Select, &["<Enter>"]),
+    (Action::Confirm, &["y", "Y"]),
+];
+
+const VIM_BINDINGS: &[(Action, &[&str])] = &[
+    (Action::MoveDown, &["j", "<Down>"]),
+    (Action::MoveUp, &["k", "<Up>"]),
+    (Action::PageDown, &["<C-d>", "<PageDown>"]),
+    (Action::PageUp, &["<C-u>", "<PageUp>"]),
+    (Action::MoveTop, &["gg", "<Home>"]),
+    (Action::MoveBottom, &["G", "<End>"]),
+    (Action::Open, &["l", "<Right>"]),
+    (Action::Back, &["h", "<Left>"]),
+];
+
+const EMACS_BINDINGS: &[(Action, &[&str])] = &[
+    (Action::Quit, &["q", "<C-g>", "<C-c>"]),
+    (Action::Search, &["<C-s>", "/"]),
+    (Action::MoveDown, &["<C-n>", "<Down>"]),
+    (Action::MoveUp, &["<C-p>", "<Up>"]),
+    (Action::PageDown, &["<C-v>", "<PageDown>"]),
+    (Action::PageUp, &["<M-v>", "<PageUp>"]),
+    (Action::MoveTop, &["<M-<>", "<Home>"]),
+    (Action::MoveBottom, &["<M->>", "<End>"]),
+    (Action::Open, &["<C-f>", "<Right>"]),
+    (Action::Back, &["<C-b>", "<Left>"]),
+];
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct KeyPress {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl From<KeyEvent> for KeyPress {
+    fn from(key: KeyEvent) -> KeyPress {
+        let mut modifiers: KeyModifiers = key.modifiers;
+        // 'G' is read as Shift + 'G', which is written as "G"
+        if let KeyCode::Char(_) = key.code {
+            modifiers.remove(KeyModifiers::SHIFT);
+        }
+        return KeyPress {
+            code: key.code,
+            modifiers,
+        };
+    }
+}
+
+impl KeyPress {
+    // Whether the search mode takes it as text rather than an action.
+    pub fn is_text(&self) -> bool {
+        match self.code {
+            KeyCode::Char(_) => return self.modifiers.is_empty(),
+            _ => return false,
+        }
+    }
+}
+
+const KEY_NAMES: &[(&str, KeyCode)] = &[
+    ("Enter", KeyCode::Enter),
+    ("Esc", KeyCode::Esc),
+    ("Tab", KeyCode::Tab),
+    ("Backspace", KeyCode::Backspace),
+    ("Space", KeyCode::Char(' ')),
+    ("lt", KeyCode::Char('<')),
+    ("Up", KeyCode::Up),
+    ("Down", KeyCode::Down),
+    ("Left", KeyCode::Left),
+    ("Right", KeyCode::Right),
+    ("Home", KeyCode::Home),
+    ("End", KeyCode::End),
+    ("PageUp", KeyCode::PageUp),
+    ("PageDown", KeyCode::PageDown),
+    ("Insert", KeyCode::Insert),
+    ("Delete", KeyCode::Delete),
+    ("CR", KeyCode::Enter),
+];
+
+fn parse_key_name(name: &str) -> Option<KeyCode> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Some(KeyCode::Char(c));
+    }
+    if let Some(number) = name
+        .strip_prefix('F')
+        .and_then(|number: &str| number.parse::<u8>().ok())
+    {
+        return Some(KeyCode::F(number));
+    }
+    return KEY_NAMES
+        .iter()
+        .find(|(key_name, _)| key_name.eq_ignore_ascii_case(name))
+        .map(|(_, code)| *code);
+}
+
+// In Vim's notation, e.g. "gg", "G", "<C-d>", "<M-<>" or "<PageDown>".
+pub fn parse_key_sequence(text: &str) -> Result<Vec<KeyPress>, String> {
+    let invalid_key = || -> String {
+        return format!("invalid key '{}'", text);
+    };
+    let mut keys: Vec<KeyPress> = vec![];
+    let mut rest: &str = text;
+    while let Some(c) = rest.chars().next() {
+        if c != '<' || rest.len() == 1 {
+            keys.push(KeyPress {
+                code: KeyCode::Char(c),
+                modifiers: KeyModifiers::NONE,
+            });
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+
+        let mut modifiers: KeyModifiers = KeyModifiers::NONE;
+        let mut name: &str = &rest[1..];
+        loop {
+            let modifier: KeyModifiers = match name.get(..2) {
+                Some("C-") | Some("c-") => KeyModifiers::CONTROL,
+                Some("M-") | Some("m-") | Some("A-") | Some("a-") => KeyModifiers::ALT,
+                Some("S-") | Some("s-") => KeyModifiers::SHIFT,
+                _ => break,
+            };
+            modifiers.insert(modifier);
+            name = &name[2..];
+        }
+        // "<M->>" is Alt + '>', so the name is at least one character
+        let first_len: usize = match name.chars().next() {
+            Some(first) => first.len_utf8(),
+            None => return Err(invalid_key()),
+        };
+        let end: usize = match name[first_len..].find('>') {
+            Some(end) => first_len + end,
+            None => return Err(invalid_key()),
+        };
+        let mut code: KeyCode = parse_key_name(&name[..end]).ok_or_else(invalid_key)?;
+        if let KeyCode::Char(c) = code {
+            // "<S-g>" is "G", and "<C-D>" is "<C-d>" as terminals send it
+            if modifiers.contains(KeyModifiers::SHIFT) {
+                code = KeyCode::Char(c.to_ascii_uppercase());
+            } else if modifiers.contains(KeyModifiers::CONTROL) {
+                code = KeyCode::Char(c.to_ascii_lowercase());
+            }
+            modifiers.remove(KeyModifiers::SHIFT);
+        }
+        keys.push(KeyPress { code, modifiers });
+        rest = &name[end + 1..];
+    }
+    if keys.len() == 0 {
+        return Err(invalid_key());
+    }
+    return Ok(keys);
+}
+
+// As the help shows it, e.g. 'q', Enter or Ctrl-d.
+pub fn format_key_sequence(keys: &[KeyPress]) -> String {
+    let mut text: String = String::new();
+    let mut is_text: bool = true;
+    for key in keys {
+        is_text &= key.is_text() && key.code != KeyCode::Char(' ');
+    }
+    for key in keys {
+        if key.modifiers.contains(KeyModifiers::CONTROL) {
+            text.push_str("Ctrl-");
+        }
+        if key.modifiers.contains(KeyModifiers::ALT) {
+            text.push_str("Alt-");
+        }
+        if key.modifiers.contains(KeyModifiers::SHIFT) {
+            text.push_str("Shift-");
+        }
+        match key.code {
+            KeyCode::Char(' ') => text.push_str("Space"),
+            KeyCode::Char(c) => text.push(c),
+            KeyCode::F(number) => text.push_str(&format!("F{}", number)),
+            code => match KEY_NAMES.iter().find(|(_, key_code)| *key_code == code) {
+                Some((name, _)) => text.push_str(name),
+                None => text.push('?'),
+            },
+        }
+    }
+    if is_text {
+        return format!("'{}'", text);
+    }
+    return text;
+}
+
+// One key sequence or a list of them in config.toml.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum KeyList {
+    One(String),
+    Many(Vec<String>),
+}
+
+// The keys of an action, replacing those of the preset. An empty list unbinds it.
+#[derive(Eq, PartialEq, Debug, Clone, Deserialize)]
+#[serde(try_from = "KeyList")]
+pub struct KeyBinding(pub Vec<Vec<KeyPress>>);
+
+impl TryFrom<KeyList> for KeyBinding {
+    type Error = String;
+
+    fn try_from(key_list: KeyList) -> Result<KeyBinding, String> {
+        let texts: Vec<String> = match key_list {
+            KeyList::One(text) => vec![text],
+            KeyList::Many(texts) => texts,
+        };
+        let mut key_sequences: Vec<Vec<KeyPress>> = vec![];
+        for text in texts {
+            key_sequences.push(parse_key_sequence(&text)?);
+        }
+        return Ok(KeyBinding(key_sequences));
+    }
+}
+
+pub enum KeyMatch {
+    Action(Action),
+    // the start of a longer key sequence, like the first 'g' of "gg"
+    Pending,
+    Unbound,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Keymap {
+    bindings: Vec<(Vec<KeyPress>, Action)>,
+}
+
+impl Keymap {
+    pub fn new(preset: KeyPreset, overrides: &BTreeMap<Action, KeyBinding>) -> Keymap {
+        let preset_bindings: &[(Action, &[&str])] = match preset {
+            KeyPreset::Default => &[],
+            KeyPreset::Vim => VIM_BINDINGS,
+            KeyPreset::Emacs => EMACS_BINDINGS,
+        };
+        let mut bindings: Vec<(Vec<KeyPress>, Action)> = vec![];
+        for (action, texts) in DEFAULT_BINDINGS {
+            let key_sequences: Vec<Vec<KeyPress>> = match overrides.get(action) {
+                Some(key_binding) => key_binding.0.clone(),
+                None => {
+                    let texts: &[&str] = match preset_bindings
+                        .iter()
+                        .find(|(preset_action, _)| preset_action == action)
+                    {
+                        Some((_, preset_texts)) => preset_texts,
+                        None => texts,
+                    };
+                    // the presets are checked by keymap_presets_test
+                    texts
+                        .iter()
+                        .map(|text: &&str| parse_key_sequence(text).unwrap())
+                        .collect()
+                }
+            };
+            let interrupt_keys: Vec<KeyPress> = parse_key_sequence(INTERRUPT_KEY).unwrap();
+            let is_interrupt_missing: bool = *action == Action::Quit
+                && key_sequences.len() > 0
+                && !key_sequences.contains(&interrupt_keys);
+            for keys in key_sequences {
+                bindings.push((keys, *action));
+            }
+            if is_interrupt_missing {
+                bindings.push((interrupt_keys, *action));
+            }
+        }
+        return Keymap { bindings };
+    }
+
+    pub fn get_match(&self, keys: &[KeyPress]) -> KeyMatch {
+        let mut key_match: KeyMatch = KeyMatch::Unbound;
+        for (binding_keys, action) in &self.bindings {
+            if binding_keys.as_slice() == keys {
+                return KeyMatch::Action(*action);
+            }
+            if binding_keys.starts_with(keys) {
+                key_match = KeyMatch::Pending;
+            }
+        }
+        return key_match;
+    }
+
+    pub fn get_key_action(&self, key: KeyPress) -> Option<Action> {
+        match self.get_match(&[key]) {
+            KeyMatch::Action(action) => return Some(action),
+            _ => return None,
+        }
+    }
+
+    // Adds the key to those typed so far. A sequence that can't be finished, like "gx", is
+    // dropped and its last key is read on its own.
+    pub fn read_key(&self, pending_keys: &mut Vec<KeyPress>, key: KeyPress) -> Option<Action> {
+        pending_keys.push(key);
+        loop {
+            match self.get_match(pending_keys) {
+                KeyMatch::Action(action) => {
+                    pending_keys.clear();
+                    return Some(action);
+                }
+                KeyMatch::Pending => return None,
+                KeyMatch::Unbound if pending_keys.len() > 1 => {
+                    pending_keys.clear();
+                    pending_keys.push(key);
+                }
+                KeyMatch::Unbound => {
+                    pending_keys.clear();
+                    return None;
+                }
+            }
+        }
+    }
+
+    // The first key of the action as the help shows it.
+    pub fn get_key_name(&self, action: Action) -> String {
+        match self
+            .bindings
+            .iter()
+            .find(|(_, binding_action)| *binding_action == action)
+        {
+            Some((keys, _)) => return format_key_sequence(keys),
+            None => return "no".to_owned(),
+        }
+    }
+
+    // The first key of the action that works in the search mode, where the keys that type text
+    // go to the query.
+    pub fn get_search_key_name(&self, action: Action) -> String {
+        match self.bindings.iter().find(|(keys, binding_action)| {
+            *binding_action == action && keys.len() == 1 && !keys[0].is_text()
+        }) {
+            Some((keys, _)) => return format_key_sequence(keys),
+            None => return "no".to_owned(),
+        }
+    }
+
+    // A key bound to two actions, or one that starts a longer sequence like 'g' and "gg", would
+    // leave an action unreachable.
+    pub fn check(&self) -> Result<(), String> {
+        for action in REQUIRED_ACTIONS {
+            if !self
+                .bindings
+                .iter()
+                .any(|(_, binding_action)| binding_action == action)
+            {
+                return Err(format!("keys: {} needs a key", action.get_name()));
+            }
+        }
+        for (index, (keys, action)) in self.bindings.iter().enumerate() {
+            for (other_keys, other_action) in &self.bindings[..index] {
+                if other_action == action {
+                    continue;
+                }
+                if other_keys == keys {
+                    return Err(format!(
+                        "keys: {} is bound to both {} and {}",
+                        format_key_sequence(keys),
+                        other_action.get_name(),
+                        action.get_name()
+                    ));
+                }
+                let (short, long) = if other_keys.len() < keys.len() {
+                    ((other_keys, other_action), (keys, action))
+                } else {
+                    ((keys, action), (other_keys, other_action))
+                };
+                if long.0.starts_with(short.0) {
+                    return Err(format!(
+                        "keys: {} of {} is the start of {} of {}",
+                        format_key_sequence(short.0),
+                        short.1.get_name(),
+                        format_key_sequence(long.0),
+                        long.1.get_name()
+                    ));
+                }
+            }
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode, modifiers: KeyModifiers) -> KeyPress {
+        return KeyPress { code, modifiers };
+    }
+
+    #[test]
+    fn parse_key_sequence_test() {
+        let none: KeyModifiers = KeyModifiers::NONE;
+        assert_eq!(
+            parse_key_sequence("gg").unwrap(),
+            [key(KeyCode::Char('g'), none), key(KeyCode::Char('g'), none)]
+        );
+        assert_eq!(
+            parse_key_sequence("<C-D>").unwrap(),
+            [key(KeyCode::Char('d'), KeyModifiers::CONTROL)]
+        );
+        assert_eq!(
+            parse_key_sequence("<S-g>").unwrap(),
+            parse_key_sequence("G").unwrap()
+        );
+        assert_eq!(
+            parse_key_sequence("<M->>").unwrap(),
+            [key(KeyCode::Char('>'), KeyModifiers::ALT)]
+        );
+        assert_eq!(
+            parse_key_sequence("<pagedown><lt>").unwrap(),
+            [key(KeyCode::PageDown, none), key(KeyCode::Char('<'), none)]
+        );
+        assert_eq!(
+            parse_key_sequence("<").unwrap(),
+            [key(KeyCode::Char('<'), none)]
+        );
+        assert!(parse_key_sequence("").is_err());
+        assert!(parse_key_sequence("<Foo>").is_err());
+        assert!(parse_key_sequence("<C-d").is_err());
+
+        assert_eq!(
+            format_key_sequence(&parse_key_sequence("gg").unwrap()),
+            "'gg'"
+        );
+        assert_eq!(
+            format_key_sequence(&parse_key_sequence("<C-d>").unwrap()),
+            "Ctrl-d"
+        );
+        assert_eq!(
+            format_key_sequence(&parse_key_sequence("<Enter>").unwrap()),
+            "Enter"
+        );
+
+        // a typed 'G' comes with Shift
+        let typed: KeyPress =
+            KeyPress::from(KeyEvent::new(KeyCode::Char('G'), KeyModifiers::SHIFT));
+        assert_eq!(typed, parse_key_sequence("G").unwrap()[0]);
+    }
+
+    #[test]
+    fn keymap_presets_test() {
+        for preset in [KeyPreset::Default, KeyPreset::Vim, KeyPreset::Emacs] {
+            assert_eq!(Keymap::new(preset, &BTreeMap::new()).check(), Ok(()));
+        }
+
+        // 'j' types text in the search mode, which moves with the arrow keys instead
+        let keymap: Keymap = Keymap::new(KeyPreset::Vim, &BTreeMap::new());
+        assert_eq!(keymap.get_key_name(Action::MoveDown), "'j'");
+        assert_eq!(keymap.get_search_key_name(Action::MoveDown), "Down");
+        assert_eq!(keymap.get_search_key_name(Action::Select), "Enter");
+        assert_eq!(keymap.get_key_name(Action::Confirm), "'y'");
+    }
+
+    #[test]
+    fn read_key_test() {
+        let keymap: Keymap = Keymap::new(KeyPreset::Vim, &BTreeMap::new());
+        let mut pending_keys: Vec<KeyPress> = vec![];
+        let mut read = |text: &str| -> Vec<Option<Action>> {
+            return parse_key_sequence(text)
+                .unwrap()
+                .into_iter()
+                .map(|key: KeyPress| keymap.read_key(&mut pending_keys, key))
+                .collect();
+        };
+        assert_eq!(
+            read("j<Down>"),
+            [Some(Action::MoveDown), Some(Action::MoveDown)]
+        );
+        assert_eq!(read("gg"), [None, Some(Action::MoveTop)]);
+        assert_eq!(read("gk"), [None, Some(Action::MoveUp)]);
+        assert_eq!(read("gxG"), [None, None, Some(Action::MoveBottom)]);
+        assert_eq!(
+            read("<C-d>d"),
+            [Some(Action::PageDown), Some(Action::Delete)]
+        );
+    }
+
+    #[test]
+    fn keymap_overrides_test() {
+        let mut overrides: BTreeMap<Action, KeyBinding> = BTreeMap::new();
+        overrides.insert(
+            Action::Quit,
+            KeyBinding(vec![parse_key_sequence("<Esc>").unwrap()]),
+        );
+        overrides.insert(Action::Sort, KeyBinding(vec![]));
+        let keymap: Keymap = Keymap::new(KeyPreset::Default, &overrides);
+        assert_eq!(keymap.get_key_name(Action::Quit), "Esc");
+        assert_eq!(keymap.get_key_name(Action::Sort), "no");
+        assert!(matches!(
+            keymap.get_match(&parse_key_sequence("q").unwrap()),
+            KeyMatch::Unbound
+        ));
+        // Ctrl-C quits whatever quit is bound to
+        assert!(matches!(
+            keymap.get_match(&parse_key_sequence("<C-c>").unwrap()),
+            KeyMatch::Action(Action::Quit)
+        ));
+        assert_eq!(keymap.check(), Ok(()));
+
+        for action in [Action::Quit, Action::Select] {
+            let mut overrides: BTreeMap<Action, KeyBinding> = BTreeMap::new();
+            overrides.insert(action, KeyBinding(vec![]));
+            assert_eq!(
+                Keymap::new(KeyPreset::Default, &overrides).check(),
+                Err(format!("keys: {} needs a key", action.get_name()))
+            );
+        }
+        let mut interrupt_overrides: BTreeMap<Action, KeyBinding> = BTreeMap::new();
+        interrupt_overrides.insert(
+            Action::Search,
+            KeyBinding(vec![parse_key_sequence("<C-c>").unwrap()]),
+        );
+        assert_eq!(
+            Keymap::new(KeyPreset::Default, &interrupt_overrides).check(),
+            Err("keys: Ctrl-c is bound to both quit and search".to_owned())
+        );
+
+        overrides.insert(
+            Action::Edit,
+            KeyBinding(vec![parse_key_sequence("d").unwrap()]),
+        );
+        assert_eq!(
+            Keymap::new(KeyPreset::Default, &overrides).check(),
+            Err("keys: 'd' is bound to both edit and delete".to_owned())
+        );
+        overrides.insert(
+            Action::Edit,
+            KeyBinding(vec![parse_key_sequence("g").unwrap()]),
+        );
+        assert_eq!(
+            Keymap::new(KeyPreset::Vim, &overrides).check(),
+            Err("keys: 'g' of edit is the start of 'gg' of move_top".to_owned())
+        );
+    }
+}
@@ -0,0 +1,269 @@
+use crate::hashtag::HashtagParser;
+use crate::normalize::HashtagNormalizer;
+use crate::parse_history::HistoryEntry;
+use std::io::Error;
+#[cfg(not(feature = "serde"))]
+use std::io::ErrorKind;
+
+// How the tags, list and search subcommands print what they found.
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum OutputFormat {
+    Text,
+    Json,
+    Ndjson,
+    Tsv,
+}
+
+impl OutputFormat {
+    pub fn parse(value: &str) -> Result<OutputFormat, String> {
+        match value {
+            "text" => return Ok(OutputFormat::Text),
+            "json" => return Ok(OutputFormat::Json),
+            "ndjson" => return Ok(OutputFormat::Ndjson),
+            "tsv" => return Ok(OutputFormat::Tsv),
+            _ => return Err("expected text, json, ndjson or tsv".to_owned()),
+        }
+    }
+}
+
+// Without the serde feature a record only has to be printable as text and TSV.
+#[cfg(feature = "serde")]
+pub trait Serializable: serde::Serialize {}
+#[cfg(feature = "serde")]
+impl<T: serde::Serialize> Serializable for T {}
+#[cfg(not(feature = "serde"))]
+pub trait Serializable {}
+#[cfg(not(feature = "serde"))]
+impl<T> Serializable for T {}
+
+pub trait Record: Serializable {
+    fn get_tsv_header() -> &'static [&'static str];
+    fn get_tsv_fields(&self) -> Vec<String>;
+    fn get_text(&self) -> String;
+}
+
+#[derive(Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct HashtagRecord {
+    pub hashtag: String,
+    // commands of the hashtag and its descendants
+    pub count: usize,
+}
+
+impl Record for HashtagRecord {
+    fn get_tsv_header() -> &'static [&'static str] {
+        return &["hashtag", "count"];
+    }
+
+    fn get_tsv_fields(&self) -> Vec<String> {
+        return vec![self.hashtag.to_owned(), self.count.to_string()];
+    }
+
+    fn get_text(&self) -> String {
+        return format!("{}\t{}", self.hashtag, self.count);
+    }
+}
+
+// A listed command with its normalized hashtags and what the init scripts recorded about its
+// latest run.
+#[derive(Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct CommandRecord {
+    pub command: String,
+    pub hashtags: Vec<String>,
+    pub timestamp: Option<i64>,
+    pub exit_status: Option<i32>,
+    pub cwd: Option<String>,
+    pub hostname: Option<String>,
+    pub session_id: Option<String>,
+    pub run_count: usize,
+}
+
+impl CommandRecord {
+    pub fn new(entry: &HistoryEntry, normalizer: &HashtagNormalizer) -> CommandRecord {
+        let mut hashtags: Vec<String> = vec![];
+        for hashtag in HashtagParser::new_shell(&entry.command) {
+            let hashtag_name: String = normalizer.normalize_hashtag(&hashtag);
+            if !hashtags.contains(&hashtag_name) {
+                hashtags.push(hashtag_name);
+            }
+        }
+        return CommandRecord {
+            command: entry.command.to_owned(),
+            hashtags,
+            timestamp: entry.timestamp,
+            exit_status: entry.exit_status,
+            cwd: entry.cwd.to_owned(),
+            hostname: entry.hostname.to_owned(),
+            session_id: entry.session_id.to_owned(),
+            run_count: entry.run_count,
+        };
+    }
+}
+
+fn to_tsv_field<T: ToString>(value: &Option<T>) -> String {
+    match value {
+        Some(value) => return value.to_string(),
+        None => return String::new(),
+    }
+}
+
+impl Record for CommandRecord {
+    fn get_tsv_header() -> &'static [&'static str] {
+        return &[
+            "command",
+            "hashtags",
+            "timestamp",
+            "exit_status",
+            "cwd",
+            "hostname",
+            "session_id",
+            "run_count",
+        ];
+    }
+
+    fn get_tsv_fields(&self) -> Vec<String> {
+        return vec![
+            self.command.to_owned(),
+            self.hashtags.join(" "),
+            to_tsv_field(&self.timestamp),
+            to_tsv_field(&self.exit_status),
+            to_tsv_field(&self.cwd),
+            to_tsv_field(&self.hostname),
+            to_tsv_field(&self.session_id),
+            self.run_count.to_string(),
+        ];
+    }
+
+    fn get_text(&self) -> String {
+        return self.command.to_owned();
+    }
+}
+
+// Keeps a multi-line command on one row.
+fn escape_tsv_field(field: &str) -> String {
+    return field
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r");
+}
+
+#[cfg(feature = "serde")]
+fn format_json_records<R: Record>(records: &[R], format: OutputFormat) -> Result<String, Error> {
+    if format == OutputFormat::Json {
+        return Ok(serde_json::to_string(records)? + "\n");
+    }
+    let mut output: String = String::new();
+    for record in records {
+        output.push_str(&serde_json::to_string(record)?);
+        output.push('\n');
+    }
+    return Ok(output);
+}
+
+#[cfg(not(feature = "serde"))]
+fn format_json_records<R: Record>(_records: &[R], _format: OutputFormat) -> Result<String, Error> {
+    return Err(Error::new(
+        ErrorKind::Unsupported,
+        "history-tidy was built without the serde feature, which JSON output needs",
+    ));
+}
+
+pub fn format_records<R: Record>(records: &[R], format: OutputFormat) -> Result<String, Error> {
+    let mut output: String = String::new();
+    match format {
+        OutputFormat::Text => {
+            for record in records {
+                output.push_str(&record.get_text());
+                output.push('\n');
+            }
+        }
+        OutputFormat::Json | OutputFormat::Ndjson => {
+            return format_json_records(records, format);
+        }
+        OutputFormat::Tsv => {
+            output.push_str(&R::get_tsv_header().join("\t"));
+            output.push('\n');
+            for record in records {
+                let fields: Vec<String> = record
+                    .get_tsv_fields()
+                    .iter()
+                    .map(|field: &String| escape_tsv_field(field))
+                    .collect();
+                output.push_str(&fields.join("\t"));
+                output.push('\n');
+            }
+        }
+    }
+    return Ok(output);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_command_records() -> Vec<CommandRecord> {
+        let mut entry: HistoryEntry = HistoryEntry::new("printf 'a\\tb' #Env=prod #env=prod #fmt");
+        entry.timestamp = Some(1700000000);
+        entry.exit_status = Some(0);
+        entry.cwd = Some("/tmp".to_owned());
+        entry.run_count = 2;
+        return vec![
+            CommandRecord::new(&entry, &HashtagNormalizer::default()),
+            CommandRecord::new(
+                &HistoryEntry::new("for i in 1 2\ndo\n\techo $i\ndone"),
+                &HashtagNormalizer::default(),
+            ),
+        ];
+    }
+
+    #[test]
+    fn format_records_test() {
+        let records: Vec<CommandRecord> = get_command_records();
+        assert_eq!(records[0].hashtags, ["#env=prod", "#fmt"]);
+
+        assert_eq!(
+            format_records(&records, OutputFormat::Tsv).unwrap(),
+            "command\thashtags\ttimestamp\texit_status\tcwd\thostname\tsession_id\trun_count\n\
+             printf 'a\\\\tb' #Env=prod #env=prod #fmt\t#env=prod #fmt\t1700000000\t0\t/tmp\t\t\t2\n\
+             for i in 1 2\\ndo\\n\\techo $i\\ndone\t\t\t\t\t\t\t0\n"
+        );
+
+        let hashtag_records: Vec<HashtagRecord> = vec![HashtagRecord {
+            hashtag: "#fmt".to_owned(),
+            count: 1,
+        }];
+        assert_eq!(
+            format_records(&hashtag_records, OutputFormat::Text).unwrap(),
+            "#fmt\t1\n"
+        );
+        assert_eq!(
+            format_records(&hashtag_records, OutputFormat::Tsv).unwrap(),
+            "hashtag\tcount\n#fmt\t1\n"
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn format_json_records_test() {
+        let records: Vec<CommandRecord> = get_command_records();
+        let ndjson: String = format_records(&records, OutputFormat::Ndjson).unwrap();
+        let lines: Vec<serde_json::Value> = ndjson
+            .lines()
+            .map(|line: &str| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0]["hashtags"],
+            serde_json::json!(["#env=prod", "#fmt"])
+        );
+        assert_eq!(lines[0]["timestamp"], 1700000000);
+        assert_eq!(lines[0]["cwd"], "/tmp");
+        assert_eq!(lines[1]["exit_status"], serde_json::Value::Null);
+
+        let json: serde_json::Value =
+            serde_json::from_str(&format_records(&records, OutputFormat::Json).unwrap()).unwrap();
+        assert_eq!(json, serde_json::Value::Array(lines));
+    }
+}
@@ -1,28 +1,120 @@
+#![allow(
+    clippy::needless_return,
+    clippy::len_zero,
+    clippy::bool_comparison,
+    clippy::redundant_static_lifetimes
+)]
+
+mod config;
+mod edit_history;
+mod fuzzy;
 mod hashtag;
+mod hashtag_tree;
+mod here;
+mod index_cache;
+mod keymap;
+mod listing;
+mod normalize;
 mod parse_history;
+mod query;
 mod setting;
+mod subcommand;
 mod ui;
 mod utils;
 
+use config::get_config;
+use config::Config;
+use here::HereDir;
+use index_cache::get_command_index;
+use normalize::HashtagNormalizer;
 use parse_history::*;
 use setting::command_line_setting;
+use setting::Output;
+use setting::Setting;
+use std::os::unix::io::RawFd;
 use std::process::exit;
+use subcommand::run_subcommand;
 use ui::init_ui;
+use ui::write_output;
+use ui::write_script_files;
+use utils::error_exit;
+use utils::redirect_stdout_to_tty;
+use utils::restore_stdout;
 
 fn main() {
-    command_line_setting();
-    let history_vec: Vec<String> = match get_tidy_history() {
-        Ok(history_vec) => history_vec,
+    let setting: Setting = command_line_setting();
+    // a typo in config.toml would otherwise go unnoticed behind the UI
+    let config: Config = match get_config() {
+        Ok(config) => config,
         Err(e) => {
-            println!("{}", e);
-            vec![]
+            error_exit("Failed to load config", e, 1);
+            return;
         }
     };
-    if history_vec.len() == 0 {
-        println!("No history found");
+    let normalizer: HashtagNormalizer = HashtagNormalizer::new(&config.hashtag);
+    let command_index: CommandIndex = match get_command_index(&config.history, &normalizer) {
+        Ok(command_index) => command_index,
+        Err(e) => {
+            eprintln!("{}", e);
+            CommandIndex::default()
+        }
+    };
+    if let Some(subcommand) = &setting.subcommand {
+        let command_hashmap = command_index.to_command_hashmap(None, &config.history.ignore);
+        match run_subcommand(
+            subcommand,
+            &command_hashmap,
+            &normalizer,
+            &config.history,
+            setting.format,
+        ) {
+            Ok(_) => exit(0),
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(1);
+            }
+        }
+    }
+    if command_index.all.len() == 0 {
+        eprintln!("No history found");
         exit(0);
     }
-    let command_hashmap = get_command_hashmap(history_vec);
-    init_ui(command_hashmap);
+    let command_hashmap =
+        command_index.to_command_hashmap(HereDir::current().as_ref(), &config.history.ignore);
+    let output: Output = match setting.output {
+        Some(output) => output,
+        None => {
+            let command: String =
+                init_ui(command_hashmap, setting.query, normalizer, config.clone());
+            write_script_files(&command, &config.run);
+            exit(0);
+        }
+    };
+
+    // stdout may be a pipe, as in `$(history-tidy --print)`
+    let stdout_fd: RawFd = match redirect_stdout_to_tty() {
+        Ok(stdout_fd) => stdout_fd,
+        Err(e) => {
+            error_exit("Failed to open the terminal", e, 1);
+            return;
+        }
+    };
+    let command: String = init_ui(command_hashmap, setting.query, normalizer, config.clone());
+    match restore_stdout(stdout_fd) {
+        Ok(_) => {}
+        Err(e) => {
+            error_exit("Failed to restore stdout", e, 1);
+            return;
+        }
+    }
+    if command.len() > 0 {
+        match write_output(&output, &command, &config.run) {
+            Ok(_) => {}
+            Err(e) => {
+                error_exit("Failed to write the selected command", e, 1);
+                return;
+            }
+        }
+    }
     exit(0);
 }
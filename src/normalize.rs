@@ -0,0 +1,126 @@
+use crate::config::HashtagConfig;
+use crate::hashtag::Hashtag;
+use std::collections::BTreeMap;
+use unicode_normalization::UnicodeNormalization;
+
+// Maps the spellings of a hashtag to the one name it is grouped under.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct HashtagNormalizer {
+    fold_case: bool,
+    nfkc: bool,
+    aliases: BTreeMap<String, String>,
+}
+
+impl Default for HashtagNormalizer {
+    fn default() -> HashtagNormalizer {
+        return HashtagNormalizer::new(&HashtagConfig::default());
+    }
+}
+
+impl HashtagNormalizer {
+    pub fn new(config: &HashtagConfig) -> HashtagNormalizer {
+        let mut normalizer: HashtagNormalizer = HashtagNormalizer {
+            fold_case: config.fold_case,
+            nfkc: config.nfkc,
+            aliases: BTreeMap::new(),
+        };
+        // aliases are written by hand, so "#Dkr" = "#Docker" works as well
+        normalizer.aliases = config
+            .aliases
+            .iter()
+            .map(|(alias, hashtag_name)| {
+                (
+                    normalizer.normalize_spelling(alias),
+                    normalizer.normalize_spelling(hashtag_name),
+                )
+            })
+            .collect();
+        return normalizer;
+    }
+
+    fn normalize_spelling(&self, hashtag_name: &str) -> String {
+        let hashtag_name: String = if self.nfkc {
+            hashtag_name.nfkc().collect()
+        } else {
+            hashtag_name.to_owned()
+        };
+        if !self.fold_case {
+            return hashtag_name;
+        }
+        // values like "#ticket=OPS-123" keep their case
+        match hashtag_name.find('=') {
+            Some(index) => return hashtag_name[..index].to_lowercase() + &hashtag_name[index..],
+            None => return hashtag_name.to_lowercase(),
+        }
+    }
+
+    pub fn normalize(&self, hashtag_name: &str) -> String {
+        let mut hashtag_name: String = self.normalize_spelling(hashtag_name);
+        // follows "#dkr" -> "#docker" -> "#container", stopping if the aliases loop
+        for _ in 0..self.aliases.len() {
+            let canonical_name: String =
+                if let Some(canonical_name) = self.aliases.get(&hashtag_name) {
+                    canonical_name.to_owned()
+                } else if let Some(index) = hashtag_name.find('=') {
+                    // "#environment=prod" -> "#env=prod"
+                    match self.aliases.get(&hashtag_name[..index]) {
+                        Some(canonical_key) => canonical_key.to_owned() + &hashtag_name[index..],
+                        None => break,
+                    }
+                } else {
+                    break;
+                };
+            hashtag_name = canonical_name;
+        }
+        return hashtag_name;
+    }
+
+    pub fn normalize_hashtag(&self, hashtag: &Hashtag) -> String {
+        // `Hashtag::as_ref` leaves out the "#" and the value, so the whole name is built first
+        let hashtag_name: String = hashtag.to_string();
+        return self.normalize(&hashtag_name);
+    }
+
+    // Changes whenever the same history would be grouped differently.
+    pub fn get_cache_key(&self) -> String {
+        let mut cache_key: String = format!("fold_case={} nfkc={}", self.fold_case, self.nfkc);
+        for (alias, hashtag_name) in &self.aliases {
+            cache_key.push_str(&format!("\n{}\t{}", alias, hashtag_name));
+        }
+        return cache_key;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_test() {
+        let mut config: HashtagConfig = HashtagConfig::default();
+        config
+            .aliases
+            .insert("#Dkr".to_owned(), "#docker".to_owned());
+        config
+            .aliases
+            .insert("#docker".to_owned(), "#container".to_owned());
+        config
+            .aliases
+            .insert("#environment".to_owned(), "#env".to_owned());
+        config.aliases.insert("#a".to_owned(), "#b".to_owned());
+        config.aliases.insert("#b".to_owned(), "#a".to_owned());
+
+        let normalizer: HashtagNormalizer = HashtagNormalizer::new(&config);
+        assert_eq!(normalizer.normalize("#Docker"), "#container");
+        assert_eq!(normalizer.normalize("#DKR"), "#container");
+        assert_eq!(normalizer.normalize("#Environment=Prod"), "#env=Prod");
+        assert_eq!(normalizer.normalize("#ｄｏｃｋｅｒ"), "#ｄｏｃｋｅｒ");
+        assert!(normalizer.normalize("#a") == "#a" || normalizer.normalize("#a") == "#b");
+
+        config.nfkc = true;
+        config.fold_case = false;
+        let normalizer: HashtagNormalizer = HashtagNormalizer::new(&config);
+        assert_eq!(normalizer.normalize("#ｄｏｃｋｅｒ"), "#container");
+        assert_eq!(normalizer.normalize("#Docker"), "#Docker");
+    }
+}
@@ -1,29 +1,27 @@
-use crate::hashtag::Hashtag;
 use crate::hashtag::HashtagParser;
+use crate::here::HereDir;
+use crate::normalize::HashtagNormalizer;
+use crate::utils::matches_glob;
 use dirs::home_dir;
-use std::fs::read_to_string;
+use std::collections::HashMap;
 use std::io::Error;
 use std::io::ErrorKind;
+use std::iter::Peekable;
+use std::path::PathBuf;
+use std::str::Lines;
 
 use linked_hash_map::LinkedHashMap;
 
-pub fn get_tidy_history() -> Result<Vec<String>, Error> {
+pub const ALL_HASHTAG: &str = "ALL";
+// commands run in the current directory, see HereDir
+pub const HERE_HASHTAG: &str = "Here";
+
+// ~/.history-tidy, where the config, the index and the script files are kept.
+pub fn get_tidy_dir_path() -> Result<PathBuf, Error> {
     match home_dir() {
-        Some(mut history_file_path) => {
-            history_file_path.push(".history-tidy");
-            history_file_path.push("history");
-            match read_to_string(history_file_path) {
-                Ok(history_file_content) => {
-                    let history_vec: Vec<String> = history_file_content
-                        .lines()
-                        .map(|line: &str| line.to_owned().trim().to_owned())
-                        .collect();
-                    return Ok(history_vec);
-                }
-                Err(e) => {
-                    return Err(e);
-                }
-            }
+        Some(mut tidy_dir_path) => {
+            tidy_dir_path.push(".history-tidy");
+            return Ok(tidy_dir_path);
         }
         None => {
             return Err(Error::new(ErrorKind::NotFound, "Can't get home path"));
@@ -31,62 +29,475 @@ pub fn get_tidy_history() -> Result<Vec<String>, Error> {
     };
 }
 
-pub fn get_command_hashmap(history_vec: Vec<String>) -> LinkedHashMap<String, Vec<String>> {
-    let mut command_hashmap: LinkedHashMap<String, Vec<String>> = LinkedHashMap::new();
-    let mut all: Vec<String> = vec![];
-    for history in &history_vec {
-        if history.len() == 0 {
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum HistoryFormat {
+    // one command per line, as written by `history -a`, optionally after a `#<epoch>` line
+    Bash,
+    // `: <start>:<elapsed>;<command>`, as written by zsh's EXTENDED_HISTORY option
+    Zsh,
+    // `- cmd: <command>` followed by indented `when:` and `paths:` lines, as in fish_history
+    Fish,
+}
+
+// A command together with what the init scripts recorded about its run.
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct HistoryEntry {
+    pub command: String,
+    // seconds since the epoch when the command was run
+    pub timestamp: Option<i64>,
+    pub exit_status: Option<i32>,
+    // directory the command was run in
+    pub cwd: Option<String>,
+    pub hostname: Option<String>,
+    // tells apart the shells that wrote to the same history file
+    pub session_id: Option<String>,
+    // how many times the command was run, counted by CommandIndex::add
+    pub run_count: usize,
+}
+
+impl HistoryEntry {
+    pub fn new(command: &str) -> HistoryEntry {
+        return HistoryEntry {
+            command: command.to_owned(),
+            ..HistoryEntry::default()
+        };
+    }
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct HistoryRecord {
+    pub format: HistoryFormat,
+    // text written in front of the command, e.g. ": 1690000000:0;"
+    pub prefix: String,
+    pub entry: HistoryEntry,
+    // lines written after the command, e.g. fish's "\n  when: 1690000000"
+    pub suffix: String,
+}
+
+impl HistoryRecord {
+    pub fn to_history_string(&self) -> String {
+        match self.format {
+            HistoryFormat::Bash => {
+                return self.prefix.to_owned() + &self.entry.command;
+            }
+            HistoryFormat::Zsh => {
+                return self.prefix.to_owned() + &self.entry.command.replace('\n', "\\\n");
+            }
+            HistoryFormat::Fish => {
+                let command: String = self
+                    .entry
+                    .command
+                    .replace('\\', "\\\\")
+                    .replace('\n', "\\n");
+                return self.prefix.to_owned() + &command + &self.suffix;
+            }
+        }
+    }
+
+    // Whether the shell is done writing the record, which only the last record of a file may
+    // not be: a metadata line without its command, a zsh command ending in a backslash before
+    // its next line, or a fish command without its attributes.
+    pub fn is_complete(&self) -> bool {
+        match self.format {
+            HistoryFormat::Bash => return self.entry.command.len() > 0 || self.prefix.len() == 0,
+            HistoryFormat::Zsh => return !self.entry.command.ends_with('\\'),
+            HistoryFormat::Fish => return self.suffix.len() > 0,
+        }
+    }
+}
+
+const FISH_COMMAND_PREFIX: &str = "- cmd: ";
+
+fn unescape_fish_command(command: &str) -> String {
+    let mut unescaped_command: String = String::new();
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped_command.push(c);
             continue;
         }
+        match chars.next() {
+            Some('n') => unescaped_command.push('\n'),
+            Some(escaped) => unescaped_command.push(escaped),
+            None => unescaped_command.push(c),
+        }
+    }
+    return unescaped_command;
+}
+
+// `#<epoch>` as written by bash, optionally followed by the tab separated
+// `<exit status>\t<hostname>\t<session id>\t<cwd>` that the init scripts add.
+fn parse_metadata_line(line: &str) -> Option<HistoryEntry> {
+    let mut fields = line.strip_prefix('#')?.splitn(5, '\t');
+    let timestamp: &str = fields.next()?;
+    if timestamp.len() == 0 || !timestamp.chars().all(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    let exit_status: Option<i32> = fields.next().and_then(|s: &str| s.parse::<i32>().ok());
+    let mut next_field = || {
+        fields
+            .next()
+            .filter(|s: &&str| s.len() > 0)
+            .map(|s: &str| s.to_owned())
+    };
+    return Some(HistoryEntry {
+        command: String::new(),
+        timestamp: timestamp.parse::<i64>().ok(),
+        exit_status,
+        hostname: next_field(),
+        session_id: next_field(),
+        cwd: next_field(),
+        run_count: 0,
+    });
+}
+
+fn get_fish_timestamp(suffix: &str) -> Option<i64> {
+    for line in suffix.lines() {
+        if let Some(timestamp) = line.strip_prefix("  when: ") {
+            return timestamp.trim().parse::<i64>().ok();
+        }
+    }
+    return None;
+}
+
+fn get_zsh_prefix_len(line: &str) -> Option<usize> {
+    let rest: &str = line.strip_prefix(": ")?;
+    let (start, rest) = rest.split_once(':')?;
+    let (elapsed, _) = rest.split_once(';')?;
+    let is_number = |s: &str| s.len() > 0 && s.chars().all(|c: char| c.is_ascii_digit());
+    if !is_number(start) || !is_number(elapsed) {
+        return None;
+    }
+    return Some(": ".len() + start.len() + ":".len() + elapsed.len() + ";".len());
+}
 
-        match all.iter().position(|v: &String| v == history) {
-            Some(i) => {
-                let latest_history: String = all.remove(i);
-                all.push(latest_history);
+fn parse_record(line: &str, lines: &mut Peekable<Lines>) -> HistoryRecord {
+    if let Some(command) = line.strip_prefix(FISH_COMMAND_PREFIX) {
+        // the attributes of a fish record are the indented lines that follow it
+        let mut suffix: String = String::new();
+        while let Some(next_line) = lines.peek() {
+            if !next_line.starts_with("  ") {
+                break;
             }
-            None => all.push(history.to_owned()),
+            suffix.push('\n');
+            suffix.push_str(next_line);
+            lines.next();
         }
+        return HistoryRecord {
+            format: HistoryFormat::Fish,
+            prefix: FISH_COMMAND_PREFIX.to_owned(),
+            entry: HistoryEntry {
+                timestamp: get_fish_timestamp(&suffix),
+                ..HistoryEntry::new(&unescape_fish_command(command))
+            },
+            suffix,
+        };
+    }
 
-        let hashtags: Vec<Hashtag> = HashtagParser::new(&history).collect::<Vec<Hashtag>>();
+    match get_zsh_prefix_len(line) {
+        Some(prefix_len) => {
+            let mut command: String = line[prefix_len..].to_owned();
+            // multi-line commands are saved with a backslash before each new line
+            while command.ends_with('\\') {
+                match lines.next() {
+                    Some(next_line) => {
+                        command.pop();
+                        command.push('\n');
+                        command.push_str(next_line);
+                    }
+                    None => break,
+                }
+            }
+            let timestamp: Option<i64> = line[": ".len()..]
+                .split(':')
+                .next()
+                .and_then(|start: &str| start.parse::<i64>().ok());
+            return HistoryRecord {
+                format: HistoryFormat::Zsh,
+                prefix: line[..prefix_len].to_owned(),
+                entry: HistoryEntry {
+                    timestamp,
+                    ..HistoryEntry::new(&command)
+                },
+                suffix: String::new(),
+            };
+        }
+        None => {
+            return HistoryRecord {
+                format: HistoryFormat::Bash,
+                prefix: String::new(),
+                entry: HistoryEntry::new(line),
+                suffix: String::new(),
+            };
+        }
+    }
+}
 
-        for hashtag in hashtags {
-            let hashtag: String = "#".to_owned() + &hashtag.text.to_owned().to_owned();
-            if command_hashmap.contains_key(&hashtag) == false {
-                let mut map_hashtag: Vec<String> = vec![];
-                map_hashtag.push(history.to_owned());
-                command_hashmap.insert(hashtag, map_hashtag);
-            } else {
-                let map_hashtag: &mut Vec<String> = command_hashmap.get_mut(&hashtag).unwrap();
+pub fn parse_history(history_file_content: &str) -> Vec<HistoryRecord> {
+    let mut history_records: Vec<HistoryRecord> = vec![];
+    let mut lines: Peekable<Lines> = history_file_content.lines().peekable();
+    while let Some(line) = lines.next() {
+        let metadata: HistoryEntry = match parse_metadata_line(line) {
+            Some(metadata) => metadata,
+            None => {
+                history_records.push(parse_record(line, &mut lines));
+                continue;
+            }
+        };
 
-                match map_hashtag
-                    .into_iter()
-                    .position(|item: &mut String| item == history)
-                {
-                    Some(i) => {
-                        let latest_history: String = map_hashtag.remove(i);
-                        map_hashtag.push(latest_history);
+        // the metadata line belongs to the record that follows it, whatever its format
+        let has_command: bool = match lines.peek() {
+            Some(next_line) => parse_metadata_line(next_line).is_none(),
+            None => false,
+        };
+        if has_command {
+            let command_line: &str = lines.next().unwrap();
+            let mut history_record: HistoryRecord = parse_record(command_line, &mut lines);
+            history_record.prefix = line.to_owned() + "\n" + &history_record.prefix;
+            history_record.entry = HistoryEntry {
+                command: history_record.entry.command,
+                timestamp: metadata.timestamp.or(history_record.entry.timestamp),
+                ..metadata
+            };
+            history_records.push(history_record);
+        } else {
+            history_records.push(HistoryRecord {
+                format: HistoryFormat::Bash,
+                prefix: line.to_owned(),
+                entry: metadata,
+                suffix: String::new(),
+            });
+        }
+    }
+    return history_records;
+}
+
+// Parses history file content into entries, the way the rest of history-tidy sees them.
+pub fn get_tidy_history(history_file_content: &str) -> Vec<HistoryEntry> {
+    return get_history_entries(parse_history(history_file_content));
+}
+
+pub fn get_history_entries(history_records: Vec<HistoryRecord>) -> Vec<HistoryEntry> {
+    return history_records
+        .into_iter()
+        .map(|history_record: HistoryRecord| {
+            let mut entry: HistoryEntry = history_record.entry;
+            entry.command = entry.command.trim().to_owned();
+            entry
+        })
+        .collect();
+}
+
+// Command ids in the order they were last run. Inserting an id that is already there moves it
+// to the end, so the map works as an ordered set.
+pub type CommandIdSet = LinkedHashMap<usize, ()>;
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct IndexedCommand {
+    // the latest run of the command
+    pub entry: HistoryEntry,
+    pub hashtags: Vec<String>,
+    // the directories the command was run in, with the number of the last run there
+    pub cwds: Vec<(String, u64)>,
+}
+
+// The history grouped by hashtag as CommandIndex::add builds it up, kept apart from the views so
+// that it can be cached on disk and extended with the commands appended since.
+#[derive(Eq, PartialEq, Debug, Default)]
+pub struct CommandIndex {
+    // interned commands, indexed by command id
+    pub commands: Vec<IndexedCommand>,
+    pub hashtag_ids: LinkedHashMap<String, CommandIdSet>,
+    pub all: CommandIdSet,
+    // how many runs were added, used to number them
+    pub run_total: u64,
+    // how the hashtags of the commands were grouped
+    pub normalizer: HashtagNormalizer,
+    command_ids: HashMap<String, usize>,
+}
+
+impl CommandIndex {
+    pub fn new(normalizer: HashtagNormalizer) -> CommandIndex {
+        return CommandIndex {
+            normalizer,
+            ..CommandIndex::default()
+        };
+    }
+
+    pub fn from_parts(
+        commands: Vec<IndexedCommand>,
+        hashtag_ids: LinkedHashMap<String, CommandIdSet>,
+        all: CommandIdSet,
+        run_total: u64,
+        normalizer: HashtagNormalizer,
+    ) -> CommandIndex {
+        let command_ids: HashMap<String, usize> = commands
+            .iter()
+            .enumerate()
+            .map(|(id, command): (usize, &IndexedCommand)| (command.entry.command.to_owned(), id))
+            .collect();
+        return CommandIndex {
+            commands,
+            hashtag_ids,
+            all,
+            run_total,
+            normalizer,
+            command_ids,
+        };
+    }
+
+    // Each distinct command is stored and parsed once, so adding stays constant time.
+    pub fn add(&mut self, mut history: HistoryEntry) {
+        if history.command.len() == 0 {
+            return;
+        }
+        self.run_total += 1;
+
+        let id: usize = match self.command_ids.get(&history.command) {
+            Some(&id) => {
+                history.run_count = self.commands[id].entry.run_count + 1;
+                id
+            }
+            None => {
+                let mut hashtags: Vec<String> = vec![];
+                for hashtag in HashtagParser::new_shell(&history.command) {
+                    // "#Docker #docker" is tagged once
+                    let hashtag: String = self.normalizer.normalize_hashtag(&hashtag);
+                    if !hashtags.contains(&hashtag) {
+                        hashtags.push(hashtag);
                     }
-                    None => map_hashtag.push(history.to_owned()),
+                }
+                history.run_count = 1;
+                self.command_ids
+                    .insert(history.command.to_owned(), self.commands.len());
+                self.commands.push(IndexedCommand {
+                    entry: HistoryEntry::new(&history.command),
+                    hashtags,
+                    cwds: vec![],
+                });
+                self.commands.len() - 1
+            }
+        };
+
+        let command: &mut IndexedCommand = &mut self.commands[id];
+        if let Some(cwd) = &history.cwd {
+            match command.cwds.iter_mut().find(|(dir, _)| dir == cwd) {
+                Some((_, last_run)) => *last_run = self.run_total,
+                None => command.cwds.push((cwd.to_owned(), self.run_total)),
+            }
+        }
+        command.entry = history;
+
+        self.all.insert(id, ());
+        for hashtag in &self.commands[id].hashtags {
+            // get_mut keeps the hashtag where it first appeared, insert would move it
+            match self.hashtag_ids.get_mut(hashtag) {
+                Some(ids) => {
+                    ids.insert(id, ());
+                }
+                None => {
+                    let mut ids: CommandIdSet = LinkedHashMap::new();
+                    ids.insert(id, ());
+                    self.hashtag_ids.insert(hashtag.to_owned(), ids);
                 }
             }
         }
     }
 
-    command_hashmap.insert("ALL".to_owned(), all);
-    return command_hashmap;
+    pub fn extend(&mut self, history_vec: Vec<HistoryEntry>) {
+        for history in history_vec {
+            self.add(history);
+        }
+    }
+
+    // A command run again moves to the end of each group, carrying the latest run's metadata.
+    // Commands matching `ignore_patterns` are left out, and so are the hashtags left with none.
+    pub fn to_command_hashmap(
+        &self,
+        here_dir: Option<&HereDir>,
+        ignore_patterns: &[String],
+    ) -> LinkedHashMap<String, Vec<HistoryEntry>> {
+        let ignored: Vec<bool> = self
+            .commands
+            .iter()
+            .map(|command: &IndexedCommand| {
+                ignore_patterns
+                    .iter()
+                    .any(|pattern: &String| matches_glob(pattern, &command.entry.command))
+            })
+            .collect();
+        let get_entries = |ids: &mut dyn Iterator<Item = usize>| -> Vec<HistoryEntry> {
+            return ids
+                .filter(|id: &usize| !ignored[*id])
+                .map(|id: usize| self.commands[id].entry.to_owned())
+                .collect();
+        };
+        let mut command_hashmap: LinkedHashMap<String, Vec<HistoryEntry>> = LinkedHashMap::new();
+        for (hashtag, ids) in &self.hashtag_ids {
+            let entries: Vec<HistoryEntry> = get_entries(&mut ids.keys().copied());
+            if entries.len() > 0 {
+                command_hashmap.insert(hashtag.to_owned(), entries);
+            }
+        }
+        command_hashmap.insert(
+            ALL_HASHTAG.to_owned(),
+            get_entries(&mut self.all.keys().copied()),
+        );
+
+        if let Some(here_dir) = here_dir {
+            // a command counts as run here if any of its runs was, not only the latest one
+            let mut here: Vec<(u64, usize)> = vec![];
+            for (id, command) in self.commands.iter().enumerate() {
+                let last_run: Option<u64> = command
+                    .cwds
+                    .iter()
+                    .filter(|(dir, _)| here_dir.contains(dir))
+                    .map(|(_, last_run)| *last_run)
+                    .max();
+                if let Some(last_run) = last_run {
+                    here.push((last_run, id));
+                }
+            }
+            here.sort();
+            let entries: Vec<HistoryEntry> = get_entries(&mut here.into_iter().map(|(_, id)| id));
+            if entries.len() > 0 {
+                command_hashmap.insert(HERE_HASHTAG.to_owned(), entries);
+            }
+        }
+        return command_hashmap;
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn get_command_hashmap(
+        history_vec: Vec<HistoryEntry>,
+        here_dir: Option<&HereDir>,
+    ) -> LinkedHashMap<String, Vec<HistoryEntry>> {
+        let mut command_index: CommandIndex = CommandIndex::default();
+        command_index.extend(history_vec);
+        return command_index.to_command_hashmap(here_dir, &[]);
+    }
+
     #[test]
+    #[allow(clippy::useless_vec)]
     fn get_tidy_history_test() {
-        let history: Vec<String> = vec!["ls -a", "pwd #hoge", "cd ~ #hoge #fuga", "ls -a"]
+        let history: Vec<HistoryEntry> = vec!["ls -a", "pwd #hoge", "cd ~ #hoge #fuga", "ls -a"]
             .iter()
-            .map(|s: &&str| s.to_string())
+            .map(|s: &&str| HistoryEntry::new(s))
             .collect();
-        let command_hashmap = get_command_hashmap(history);
+        let command_hashmap = get_command_hashmap(history, None)
+            .into_iter()
+            .map(|(hashtag, entries)| {
+                let commands: Vec<String> = entries
+                    .into_iter()
+                    .map(|entry: HistoryEntry| entry.command)
+                    .collect();
+                (hashtag, commands)
+            })
+            .collect::<LinkedHashMap<String, Vec<String>>>();
 
         let expected_command_hashmap = vec![
             ("#hoge", vec!["pwd #hoge", "cd ~ #hoge #fuga"]),
@@ -106,4 +517,316 @@ mod tests {
 
         assert_eq!(command_hashmap, expected_command_hashmap);
     }
+
+    // A history where commands repeat and share hashtags, the same for the same seed.
+    fn generate_history(len: usize, seed: u64) -> Vec<HistoryEntry> {
+        let mut state: u64 = seed;
+        let mut next = |n: u64| -> u64 {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            return (state >> 33) % n;
+        };
+        return (0..len)
+            .map(|_| {
+                let command: String = format!(
+                    "cmd{} #tag{} #tag{}",
+                    next(len as u64 / 2 + 1),
+                    next(50),
+                    next(50)
+                );
+                HistoryEntry::new(&command)
+            })
+            .collect();
+    }
+
+    #[test]
+    fn get_command_hashmap_order_test() {
+        // the quadratic build this replaced, kept as the reference for the ordering
+        let history: Vec<HistoryEntry> = generate_history(3000, 1);
+        let mut expected: LinkedHashMap<String, Vec<String>> = LinkedHashMap::new();
+        let mut all: Vec<String> = vec![];
+        for history in &history {
+            let command: &String = &history.command;
+            if let Some(i) = all.iter().position(|v: &String| v == command) {
+                all.remove(i);
+            }
+            all.push(command.to_owned());
+            for hashtag in HashtagParser::new_shell(command) {
+                let hashtag: String = hashtag.to_string();
+                if expected.contains_key(&hashtag) == false {
+                    expected.insert(hashtag, vec![command.to_owned()]);
+                } else {
+                    let commands: &mut Vec<String> = expected.get_mut(&hashtag).unwrap();
+                    if let Some(i) = commands.iter().position(|v: &String| v == command) {
+                        commands.remove(i);
+                    }
+                    commands.push(command.to_owned());
+                }
+            }
+        }
+        expected.insert(ALL_HASHTAG.to_owned(), all);
+
+        let command_hashmap: LinkedHashMap<String, Vec<String>> =
+            get_command_hashmap(history, None)
+                .into_iter()
+                .map(|(hashtag, entries)| {
+                    let commands: Vec<String> = entries
+                        .into_iter()
+                        .map(|entry: HistoryEntry| entry.command)
+                        .collect();
+                    (hashtag, commands)
+                })
+                .collect();
+        assert_eq!(command_hashmap, expected);
+    }
+
+    // timing varies with the machine and the load on it, `cargo bench` measures it as well
+    #[test]
+    #[ignore]
+    fn get_command_hashmap_scale_test() {
+        let measure = |len: usize| -> std::time::Duration {
+            let history: Vec<HistoryEntry> = generate_history(len, 2);
+            let start: std::time::Instant = std::time::Instant::now();
+            let command_hashmap = get_command_hashmap(history, None);
+            let elapsed: std::time::Duration = start.elapsed();
+            assert!(command_hashmap.get(ALL_HASHTAG).unwrap().len() > len / 4);
+            return elapsed;
+        };
+        // 4 times the lines take about 4 times as long; a quadratic build would take 16 times
+        let small: std::time::Duration = measure(25_000);
+        let large: std::time::Duration = measure(100_000);
+        assert!(
+            large < small * 10,
+            "25k lines: {:?}, 100k lines: {:?}",
+            small,
+            large
+        );
+    }
+
+    #[test]
+    fn get_here_commands_test() {
+        let entry = |command: &str, cwd: &str| -> HistoryEntry {
+            return HistoryEntry {
+                cwd: Some(cwd.to_owned()),
+                ..HistoryEntry::new(command)
+            };
+        };
+        let history: Vec<HistoryEntry> = vec![
+            entry("make deploy #release", "/src/app"),
+            entry("cargo test", "/src/app/crate"),
+            entry("ls", "/tmp"),
+            entry("make deploy #release", "/src/other"),
+            HistoryEntry::new("pwd"),
+        ];
+        let here_dir: HereDir = HereDir {
+            path: PathBuf::from("/src/app"),
+            is_worktree: true,
+        };
+        let command_hashmap = get_command_hashmap(history, Some(&here_dir));
+        let here: Vec<&str> = command_hashmap
+            .get(HERE_HASHTAG)
+            .unwrap()
+            .iter()
+            .map(|entry: &HistoryEntry| entry.command.as_str())
+            .collect();
+        assert_eq!(here, vec!["make deploy #release", "cargo test"]);
+
+        let here_dir: HereDir = HereDir {
+            path: PathBuf::from("/home"),
+            is_worktree: false,
+        };
+        let command_hashmap = get_command_hashmap(vec![entry("ls", "/tmp")], Some(&here_dir));
+        assert_eq!(command_hashmap.get(HERE_HASHTAG), None);
+    }
+
+    #[test]
+    fn ignore_commands_test() {
+        let mut command_index: CommandIndex = CommandIndex::default();
+        command_index.extend(
+            ["ls", "cd /tmp #nav", "make #build", "cd ~ #nav #home"]
+                .iter()
+                .map(|s: &&str| HistoryEntry::new(s))
+                .collect(),
+        );
+        let command_hashmap =
+            command_index.to_command_hashmap(None, &["ls".to_owned(), "cd * #home".to_owned()]);
+        let get_commands = |hashtag: &str| -> Vec<&str> {
+            return command_hashmap[hashtag]
+                .iter()
+                .map(|entry: &HistoryEntry| entry.command.as_str())
+                .collect();
+        };
+        assert_eq!(get_commands(ALL_HASHTAG), ["cd /tmp #nav", "make #build"]);
+        assert_eq!(get_commands("#nav"), ["cd /tmp #nav"]);
+        assert_eq!(command_hashmap.get("#home"), None);
+    }
+
+    #[test]
+    fn parse_history_test() {
+        let history_file_content: &str = "ls -a
+: 1690000000:0;pwd #hoge
+: 1690000001:3;for i in 1 2; do\\
+  echo $i\\
+done #loop
+cd ~
+";
+        let history_records: Vec<HistoryRecord> = parse_history(history_file_content);
+        let commands: Vec<&str> = history_records
+            .iter()
+            .map(|history_record: &HistoryRecord| history_record.entry.command.as_str())
+            .collect();
+        assert_eq!(
+            commands,
+            vec![
+                "ls -a",
+                "pwd #hoge",
+                "for i in 1 2; do\n  echo $i\ndone #loop",
+                "cd ~"
+            ]
+        );
+        assert_eq!(history_records[2].prefix, ": 1690000001:3;");
+
+        let restored: Vec<String> = history_records
+            .iter()
+            .map(|history_record: &HistoryRecord| history_record.to_history_string())
+            .collect();
+        assert_eq!(restored.join("\n") + "\n", history_file_content);
+    }
+
+    #[test]
+    fn parse_fish_history_test() {
+        let history_file_content: &str = "- cmd: ls -a #file
+  when: 1690000000
+- cmd: echo \\\\ | tr a b\\nprintf done #loop
+  when: 1690000001
+  paths:
+    - ./src
+ls -a
+";
+        let history_records: Vec<HistoryRecord> = parse_history(history_file_content);
+        let commands: Vec<&str> = history_records
+            .iter()
+            .map(|history_record: &HistoryRecord| history_record.entry.command.as_str())
+            .collect();
+        assert_eq!(
+            commands,
+            vec![
+                "ls -a #file",
+                "echo \\ | tr a b\nprintf done #loop",
+                "ls -a"
+            ]
+        );
+        assert_eq!(history_records[0].format, HistoryFormat::Fish);
+        assert_eq!(
+            history_records[1].suffix,
+            "\n  when: 1690000001\n  paths:\n    - ./src"
+        );
+        assert_eq!(history_records[2].format, HistoryFormat::Bash);
+
+        let restored: Vec<String> = history_records
+            .iter()
+            .map(|history_record: &HistoryRecord| history_record.to_history_string())
+            .collect();
+        assert_eq!(restored.join("\n") + "\n", history_file_content);
+    }
+
+    #[test]
+    fn parse_history_timestamp_test() {
+        let history_file_content: &str = "#1690000000
+ls -a
+pwd
+: 1690000100:0;cd ~
+- cmd: make #build
+  when: 1690000200
+#1690000300
+#1690000400
+ls -a
+";
+        let history_records: Vec<HistoryRecord> = parse_history(history_file_content);
+        let timestamps: Vec<(&str, Option<i64>)> = history_records
+            .iter()
+            .map(|history_record: &HistoryRecord| {
+                (
+                    history_record.entry.command.as_str(),
+                    history_record.entry.timestamp,
+                )
+            })
+            .collect();
+        assert_eq!(
+            timestamps,
+            vec![
+                ("ls -a", Some(1690000000)),
+                ("pwd", None),
+                ("cd ~", Some(1690000100)),
+                ("make #build", Some(1690000200)),
+                ("", Some(1690000300)),
+                ("ls -a", Some(1690000400)),
+            ]
+        );
+
+        let restored: Vec<String> = history_records
+            .iter()
+            .map(|history_record: &HistoryRecord| history_record.to_history_string())
+            .collect();
+        assert_eq!(restored.join("\n") + "\n", history_file_content);
+
+        // the latest run of a command is the one that is kept
+        let command_hashmap = get_command_hashmap(
+            history_records
+                .into_iter()
+                .map(|history_record: HistoryRecord| history_record.entry)
+                .collect(),
+            None,
+        );
+        let all: &Vec<HistoryEntry> = command_hashmap.get(ALL_HASHTAG).unwrap();
+        assert_eq!(all.last().unwrap().command, "ls -a");
+        assert_eq!(all.last().unwrap().timestamp, Some(1690000400));
+        assert_eq!(all.last().unwrap().run_count, 2);
+    }
+
+    #[test]
+    fn parse_history_metadata_test() {
+        let history_file_content: &str =
+            "#1690000000\t0\tlaptop\t4242-1689999000\t/home/user/my project
+make #build
+#1690000100\t2\tlaptop\t4242-1689999000\t/tmp
+: 1690000099:1;ls nothing
+#1690000200\t0\t\t\t
+- cmd: pwd
+  when: 1690000200
+";
+        let history_records: Vec<HistoryRecord> = parse_history(history_file_content);
+        assert_eq!(
+            history_records
+                .iter()
+                .map(|history_record: &HistoryRecord| history_record.format)
+                .collect::<Vec<HistoryFormat>>(),
+            vec![HistoryFormat::Bash, HistoryFormat::Zsh, HistoryFormat::Fish]
+        );
+        assert_eq!(
+            history_records[0].entry,
+            HistoryEntry {
+                command: "make #build".to_owned(),
+                timestamp: Some(1690000000),
+                exit_status: Some(0),
+                cwd: Some("/home/user/my project".to_owned()),
+                hostname: Some("laptop".to_owned()),
+                session_id: Some("4242-1689999000".to_owned()),
+                run_count: 0,
+            }
+        );
+        assert_eq!(history_records[1].entry.command, "ls nothing");
+        assert_eq!(history_records[1].entry.timestamp, Some(1690000100));
+        assert_eq!(history_records[1].entry.exit_status, Some(2));
+        assert_eq!(history_records[1].entry.cwd, Some("/tmp".to_owned()));
+        assert_eq!(history_records[2].entry.hostname, None);
+        assert_eq!(history_records[2].entry.cwd, None);
+
+        let restored: Vec<String> = history_records
+            .iter()
+            .map(|history_record: &HistoryRecord| history_record.to_history_string())
+            .collect();
+        assert_eq!(restored.join("\n") + "\n", history_file_content);
+    }
 }
@@ -0,0 +1,389 @@
+use crate::hashtag_tree::get_child_hashtags;
+use crate::hashtag_tree::get_hashtag_groups;
+use crate::normalize::HashtagNormalizer;
+use crate::parse_history::HistoryEntry;
+use crate::parse_history::ALL_HASHTAG;
+use linked_hash_map::LinkedHashMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::iter::Peekable;
+use std::str::Chars;
+
+#[derive(Eq, PartialEq, Debug)]
+pub enum Query {
+    Hashtag(String),
+    Not(Box<Query>),
+    And(Box<Query>, Box<Query>),
+    Or(Box<Query>, Box<Query>),
+}
+
+#[derive(Eq, PartialEq, Debug)]
+enum Token {
+    Hashtag(String),
+    And,
+    Or,
+    Not,
+    OpenParen,
+    CloseParen,
+}
+
+fn is_operator(c: char) -> bool {
+    return c == '&' || c == '|' || c == '!' || c == '(' || c == ')';
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens: Vec<Token> = vec![];
+    let mut chars: Peekable<Chars> = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '&' | '|' => {
+                // accept both `&` and `&&`, `|` and `||`
+                if chars.peek() == Some(&c) {
+                    chars.next();
+                }
+                tokens.push(if c == '&' { Token::And } else { Token::Or });
+            }
+            '!' => tokens.push(Token::Not),
+            '(' => tokens.push(Token::OpenParen),
+            ')' => tokens.push(Token::CloseParen),
+            '#' => {
+                let mut hashtag: String = "#".to_owned();
+                while let Some(&next) = chars.peek() {
+                    if next.is_whitespace() || is_operator(next) {
+                        break;
+                    }
+                    hashtag.push(next);
+                    chars.next();
+                }
+                if hashtag.len() == 1 {
+                    return Err("empty hashtag".to_owned());
+                }
+                tokens.push(Token::Hashtag(hashtag));
+            }
+            _ => {
+                if c.is_whitespace() {
+                    continue;
+                }
+                // `env=prod` is short for `#env=prod`
+                let mut hashtag: String = "#".to_owned();
+                hashtag.push(c);
+                while let Some(&next) = chars.peek() {
+                    if next.is_whitespace() || is_operator(next) {
+                        break;
+                    }
+                    hashtag.push(next);
+                    chars.next();
+                }
+                if !hashtag.contains('=') {
+                    return Err(format!("unexpected '{}'", c));
+                }
+                tokens.push(Token::Hashtag(hashtag));
+            }
+        }
+    }
+    return Ok(tokens);
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        return self.tokens.get(self.position);
+    }
+
+    fn parse_or(&mut self) -> Result<Query, String> {
+        let mut query: Query = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.position += 1;
+            query = Query::Or(Box::new(query), Box::new(self.parse_and()?));
+        }
+        return Ok(query);
+    }
+
+    fn parse_and(&mut self) -> Result<Query, String> {
+        let mut query: Query = self.parse_not()?;
+        while self.peek() == Some(&Token::And) {
+            self.position += 1;
+            query = Query::And(Box::new(query), Box::new(self.parse_not()?));
+        }
+        return Ok(query);
+    }
+
+    fn parse_not(&mut self) -> Result<Query, String> {
+        self.position += 1;
+        match self.tokens.get(self.position - 1) {
+            Some(Token::Not) => {
+                return Ok(Query::Not(Box::new(self.parse_not()?)));
+            }
+            Some(Token::OpenParen) => {
+                let query: Query = self.parse_or()?;
+                if self.peek() != Some(&Token::CloseParen) {
+                    return Err("missing ')'".to_owned());
+                }
+                self.position += 1;
+                return Ok(query);
+            }
+            Some(Token::Hashtag(hashtag)) => {
+                return Ok(Query::Hashtag(hashtag.to_owned()));
+            }
+            Some(_) => {
+                return Err("expected hashtag".to_owned());
+            }
+            None => {
+                return Err("unexpected end of query".to_owned());
+            }
+        }
+    }
+}
+
+impl Query {
+    pub fn parse(text: &str) -> Result<Query, String> {
+        let mut parser: Parser = Parser {
+            tokens: tokenize(text)?,
+            position: 0,
+        };
+        let query: Query = parser.parse_or()?;
+        if parser.position != parser.tokens.len() {
+            return Err("unexpected token after query".to_owned());
+        }
+        return Ok(query);
+    }
+
+    fn hashtags(&self) -> Vec<&str> {
+        match self {
+            Query::Hashtag(hashtag) => vec![hashtag.as_str()],
+            Query::Not(query) => query.hashtags(),
+            Query::And(left, right) | Query::Or(left, right) => {
+                let mut hashtags: Vec<&str> = left.hashtags();
+                hashtags.extend(right.hashtags());
+                hashtags
+            }
+        }
+    }
+
+    fn matches<F: Fn(&str) -> bool>(&self, has_hashtag: &F) -> bool {
+        match self {
+            Query::Hashtag(hashtag) => has_hashtag(hashtag),
+            Query::Not(query) => !query.matches(has_hashtag),
+            Query::And(left, right) => left.matches(has_hashtag) && right.matches(has_hashtag),
+            Query::Or(left, right) => left.matches(has_hashtag) || right.matches(has_hashtag),
+        }
+    }
+
+    // Keeps the order of the ALL group, so the result reads like any other Command View.
+    pub fn filter_commands(
+        &self,
+        history_map: &LinkedHashMap<String, Vec<HistoryEntry>>,
+        normalizer: &HashtagNormalizer,
+    ) -> Vec<HistoryEntry> {
+        let child_hashtags: HashMap<String, HashSet<String>> = get_child_hashtags(history_map);
+        let mut hashtag_sets: HashMap<&str, HashSet<&str>> = HashMap::new();
+        for hashtag in self.hashtags() {
+            // the history is grouped by normalized names, so "#Docker" finds "#docker"
+            let hashtag_name: String = normalizer.normalize(hashtag);
+            // `#env=*` matches every value of the key
+            let groups: Vec<&Vec<HistoryEntry>> = match hashtag_name.strip_suffix('*') {
+                Some(key) if key.ends_with('=') => history_map
+                    .iter()
+                    .filter(|(name, _)| name.starts_with(key))
+                    .map(|(_, entries)| entries)
+                    .collect(),
+                // `#aws` matches `#aws/s3` as well, as the Command View of `#aws` lists it
+                _ => get_hashtag_groups(history_map, &child_hashtags, &hashtag_name),
+            };
+            let commands: HashSet<&str> = groups
+                .into_iter()
+                .flatten()
+                .map(|entry: &HistoryEntry| entry.command.as_str())
+                .collect();
+            hashtag_sets.insert(hashtag, commands);
+        }
+
+        let all: &[HistoryEntry] = match history_map.get(ALL_HASHTAG) {
+            Some(all) => all,
+            None => &[],
+        };
+        return all
+            .iter()
+            .filter(|entry: &&HistoryEntry| {
+                self.matches(&|hashtag: &str| {
+                    hashtag_sets[hashtag].contains(entry.command.as_str())
+                })
+            })
+            .cloned()
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_history::CommandIndex;
+
+    fn hashtag(name: &str) -> Box<Query> {
+        return Box::new(Query::Hashtag(name.to_owned()));
+    }
+
+    #[test]
+    fn parse_test() {
+        assert_eq!(
+            Query::parse("#k8s & #prod & !#readonly").unwrap(),
+            Query::And(
+                Box::new(Query::And(hashtag("#k8s"), hashtag("#prod"))),
+                Box::new(Query::Not(hashtag("#readonly")))
+            )
+        );
+        assert_eq!(
+            Query::parse("#a | #b && #c").unwrap(),
+            Query::Or(
+                hashtag("#a"),
+                Box::new(Query::And(hashtag("#b"), hashtag("#c")))
+            )
+        );
+        assert_eq!(
+            Query::parse("(#a || #b) & #c").unwrap(),
+            Query::And(
+                Box::new(Query::Or(hashtag("#a"), hashtag("#b"))),
+                hashtag("#c")
+            )
+        );
+        assert!(Query::parse("#a &").is_err());
+        assert!(Query::parse("#a #b").is_err());
+        assert!(Query::parse("(#a").is_err());
+        assert!(Query::parse("a").is_err());
+        assert!(Query::parse("").is_err());
+    }
+
+    #[test]
+    fn filter_commands_test() {
+        let history: Vec<HistoryEntry> = [
+            "kubectl get pods #k8s #prod #readonly",
+            "kubectl delete pod web #k8s #prod",
+            "kubectl apply -f . #k8s #dev",
+            "ls -a",
+        ]
+        .iter()
+        .map(|s: &&str| HistoryEntry::new(s))
+        .collect();
+        let mut command_index: CommandIndex = CommandIndex::default();
+        command_index.extend(history);
+        let command_hashmap = command_index.to_command_hashmap(None, &[]);
+        let filter_commands = |query: &Query| -> Vec<String> {
+            return query
+                .filter_commands(&command_hashmap, &HashtagNormalizer::default())
+                .into_iter()
+                .map(|entry: HistoryEntry| entry.command)
+                .collect();
+        };
+
+        let query: Query = Query::parse("#k8s & #prod & !#readonly").unwrap();
+        assert_eq!(
+            filter_commands(&query),
+            vec!["kubectl delete pod web #k8s #prod"]
+        );
+
+        let query: Query = Query::parse("#Dev | #readonly").unwrap();
+        assert_eq!(
+            filter_commands(&query),
+            vec![
+                "kubectl get pods #k8s #prod #readonly",
+                "kubectl apply -f . #k8s #dev"
+            ]
+        );
+
+        let query: Query = Query::parse("!#k8s | #unknown").unwrap();
+        assert_eq!(filter_commands(&query), vec!["ls -a"]);
+    }
+
+    #[test]
+    fn filter_descendant_commands_test() {
+        let history: Vec<HistoryEntry> = [
+            "aws s3 ls #aws/s3",
+            "aws s3 rm s3://bucket/old #aws/s3 #danger",
+            "aws configure #aws",
+            "aws-shell #awscli",
+        ]
+        .iter()
+        .map(|s: &&str| HistoryEntry::new(s))
+        .collect();
+        let mut command_index: CommandIndex = CommandIndex::default();
+        command_index.extend(history);
+        let command_hashmap = command_index.to_command_hashmap(None, &[]);
+        let filter_commands = |text: &str| -> Vec<String> {
+            return Query::parse(text)
+                .unwrap()
+                .filter_commands(&command_hashmap, &HashtagNormalizer::default())
+                .into_iter()
+                .map(|entry: HistoryEntry| entry.command)
+                .collect();
+        };
+
+        assert_eq!(
+            filter_commands("#aws"),
+            vec![
+                "aws s3 ls #aws/s3",
+                "aws s3 rm s3://bucket/old #aws/s3 #danger",
+                "aws configure #aws"
+            ]
+        );
+        assert_eq!(
+            filter_commands("#aws & !#danger"),
+            vec!["aws s3 ls #aws/s3", "aws configure #aws"]
+        );
+        assert_eq!(
+            filter_commands("#aws/s3"),
+            vec![
+                "aws s3 ls #aws/s3",
+                "aws s3 rm s3://bucket/old #aws/s3 #danger"
+            ]
+        );
+    }
+
+    #[test]
+    fn filter_key_value_commands_test() {
+        let history: Vec<HistoryEntry> = [
+            "terraform apply #env=prod",
+            "terraform plan #env=staging",
+            "terraform fmt #env",
+            "ls -a",
+        ]
+        .iter()
+        .map(|s: &&str| HistoryEntry::new(s))
+        .collect();
+        let mut command_index: CommandIndex = CommandIndex::default();
+        command_index.extend(history);
+        let command_hashmap = command_index.to_command_hashmap(None, &[]);
+        let filter_commands = |text: &str| -> Vec<String> {
+            return Query::parse(text)
+                .unwrap()
+                .filter_commands(&command_hashmap, &HashtagNormalizer::default())
+                .into_iter()
+                .map(|entry: HistoryEntry| entry.command)
+                .collect();
+        };
+
+        assert_eq!(
+            filter_commands("env=prod"),
+            vec!["terraform apply #env=prod"]
+        );
+        assert_eq!(
+            filter_commands("#env=*"),
+            vec!["terraform apply #env=prod", "terraform plan #env=staging"]
+        );
+        assert_eq!(
+            filter_commands("env=* & !env=prod"),
+            vec!["terraform plan #env=staging"]
+        );
+        assert_eq!(
+            filter_commands("#env"),
+            vec![
+                "terraform apply #env=prod",
+                "terraform plan #env=staging",
+                "terraform fmt #env"
+            ]
+        );
+    }
+}
@@ -1,5 +1,12 @@
+use crate::config::get_config;
+use crate::config::HistoryConfig;
+use crate::edit_history::parse_hashtag_input;
+use crate::listing::OutputFormat;
+use crate::query::Query;
 use colored::*;
 use std::env;
+use std::os::unix::io::RawFd;
+use std::path::PathBuf;
 use std::process::exit;
 
 const PACKAGE_NAME: &str = env!("CARGO_PKG_NAME");
@@ -13,44 +20,387 @@ fn gen_help_string() -> String {
 
 {usage}:
     {package_name} [OPTIONS]
+    {package_name} <SUBCOMMAND> [ARGS]
+
+{subcommands}:
+    {tags}                  List hashtags with their number of commands
+    {list}        List the commands of a hashtag, or all of them
+    {tag}   Add hashtags to a command in the history
+    {untag} Remove hashtags from a command in the history
+    {rename_tag}  Rename a hashtag in the whole history
+    {search}        List the commands matching a hashtag query
 
 {options}:
-    {help}      Prints help information
-    {version}   Prints version information
-    {init_bash}      Initialize bash history file",
+    {help}                Prints help information
+    {version}             Prints version information
+    {init_bash}                Initialize bash history file
+    {init_zsh}                 Initialize zsh history file
+    {init_fish}                Initialize fish history file
+    {query}   Show commands matching a hashtag query
+                          e.g. '#k8s & #prod & !#readonly', '#a | #b'
+                          '#aws' also matches '#aws/s3', '#env' '#env=prod'
+    {print}               Print the selected command instead of running it
+    {output} Write the selected command to a file descriptor or file
+                          e.g. '3', '/tmp/command'
+    {format}   Output format of tags, list and search
+                          text (default), json, ndjson or tsv",
         package_name = PACKAGE_NAME,
         package_version = PACKAGE_VERSION,
         package_description = PACKAGE_DESCRIPTION,
         usage = "USAGE".cyan().bold(),
+        subcommands = "SUBCOMMANDS".cyan().bold(),
         options = "OPTIONS".cyan().bold(),
+        tags = "tags".green(),
+        list = "list [HASHTAG]".green(),
+        tag = "tag <COMMAND> <HASHTAG>...".green(),
+        untag = "untag <COMMAND> <HASHTAG>...".green(),
+        rename_tag = "rename-tag <OLD> <NEW>".green(),
+        search = "search <QUERY>".green(),
         help = "-h, --help".green(),
         version = "-V, --version".green(),
-        init_bash = "-init-bash".green()
+        init_bash = "-init-bash".green(),
+        init_zsh = "-init-zsh".green(),
+        init_fish = "-init-fish".green(),
+        query = "-q, --query <QUERY>".green(),
+        print = "-p, --print".green(),
+        output = "-o, --output <FD|FILE>".green(),
+        format = "--format <FORMAT>".green()
+    );
+}
+
+// Where the selected command goes instead of the script file, the UI is drawn on the terminal.
+#[derive(Eq, PartialEq, Debug)]
+pub enum Output {
+    Stdout,
+    Fd(RawFd),
+    File(PathBuf),
+}
+
+impl Output {
+    // "3" is a file descriptor, anything else is a file
+    pub fn parse(value: &str) -> Output {
+        match value.parse::<RawFd>() {
+            Ok(1) => return Output::Stdout,
+            Ok(fd) if fd >= 0 => return Output::Fd(fd),
+            _ => return Output::File(PathBuf::from(value)),
+        }
+    }
+}
+
+// Runs without the UI, against the same history as the Hashtag View.
+#[derive(Eq, PartialEq, Debug)]
+pub enum Subcommand {
+    Tags,
+    List(Option<String>),
+    Tag(String, Vec<String>),
+    Untag(String, Vec<String>),
+    RenameTag(String, String),
+    Search(String),
+}
+
+impl Subcommand {
+    // the subcommands that print hashtags or commands, which `--format` is for
+    fn is_listing(&self) -> bool {
+        return matches!(
+            self,
+            Subcommand::Tags | Subcommand::List(_) | Subcommand::Search(_)
+        );
+    }
+}
+
+fn parse_single_hashtag(input: &str) -> Result<String, String> {
+    match parse_hashtag_input(input) {
+        Some(hashtag_names) if hashtag_names.len() == 1 => return Ok(hashtag_names[0].to_owned()),
+        _ => return Err(format!("Invalid hashtag '{}'", input)),
+    }
+}
+
+fn parse_hashtags(inputs: &[String]) -> Result<Vec<String>, String> {
+    let input: String = inputs.join(" ");
+    match parse_hashtag_input(&input) {
+        Some(hashtag_names) => return Ok(hashtag_names),
+        None => return Err(format!("Invalid hashtags '{}'", input)),
+    }
+}
+
+// `args` is the subcommand name followed by its arguments.
+pub fn parse_subcommand(args: &[String]) -> Result<Subcommand, String> {
+    let name: &str = &args[0];
+    match &args[1..] {
+        [] if name == "tags" => return Ok(Subcommand::Tags),
+        [] if name == "list" => return Ok(Subcommand::List(None)),
+        [hashtag] if name == "list" => {
+            return Ok(Subcommand::List(Some(parse_single_hashtag(hashtag)?)))
+        }
+        [command, hashtags @ ..] if name == "tag" && hashtags.len() > 0 => {
+            return Ok(Subcommand::Tag(
+                command.to_owned(),
+                parse_hashtags(hashtags)?,
+            ))
+        }
+        [command, hashtags @ ..] if name == "untag" && hashtags.len() > 0 => {
+            return Ok(Subcommand::Untag(
+                command.to_owned(),
+                parse_hashtags(hashtags)?,
+            ))
+        }
+        [old_hashtag, new_hashtag] if name == "rename-tag" => {
+            return Ok(Subcommand::RenameTag(
+                parse_single_hashtag(old_hashtag)?,
+                parse_single_hashtag(new_hashtag)?,
+            ))
+        }
+        [query] if name == "search" => {
+            Query::parse(query)
+                .map_err(|e: String| format!("Invalid query '{}' [{}]", query, e))?;
+            return Ok(Subcommand::Search(query.to_owned()));
+        }
+        _ => {}
+    }
+    if ["tags", "list", "tag", "untag", "rename-tag", "search"].contains(&name) {
+        return Err(format!("Wrong arguments for '{}'", name));
+    }
+    return Err(format!("Unknown subcommand '{}'", name));
+}
+
+pub struct Setting {
+    pub query: Option<String>,
+    // for the shell widgets and pipelines, the selected command is written here
+    pub output: Option<Output>,
+    pub subcommand: Option<Subcommand>,
+    pub format: OutputFormat,
+}
+
+// The init scripts append to ~/.history-tidy/history unless `history.file` says otherwise.
+fn get_init_script(script: &str, is_fish: bool) -> String {
+    let history_config: HistoryConfig = match get_config() {
+        Ok(config) => config.history,
+        Err(e) => {
+            eprintln!("{}: {}", "warning".yellow().bold(), e);
+            return script.to_owned();
+        }
+    };
+    if history_config.file == HistoryConfig::default().file {
+        return script.to_owned();
+    }
+    let history_path: String = match history_config.get_file_path() {
+        Ok(history_path) => history_path.to_string_lossy().into_owned(),
+        Err(_) => return script.to_owned(),
+    };
+    if is_fish {
+        let quoted_path: String = history_path.replace('\\', "\\\\").replace('\'', "\\'");
+        return format!(
+            "{}\nset -g history_tidy_history_path '{}'",
+            script, quoted_path
+        );
+    }
+    let quoted_path: String = history_path.replace('\'', "'\\''");
+    return format!("{}\nhistory_tidy_history_path='{}'", script, quoted_path);
+}
+
+fn unknown_argument_exit(option: &str) -> ! {
+    eprintln!(
+        "{}: Unknown argument '{}'\n",
+        "error".red().bold(),
+        option.yellow()
     );
+    println!("{}", gen_help_string());
+    exit(1);
 }
 
-pub fn command_line_setting() {
+pub fn command_line_setting() -> Setting {
     let args: Vec<String> = env::args().collect();
+    let mut setting: Setting = Setting {
+        query: None,
+        output: None,
+        subcommand: None,
+        format: OutputFormat::Text,
+    };
+    let mut format: Option<String> = None;
+    // the subcommand and its arguments, options may come before or after them
+    let mut positional_args: Vec<String> = vec![];
+    let mut options_ended: bool = false;
 
-    if args.len() >= 2 {
-        let option: &String = &args[1];
-        if option == "-h" || option == "--help" {
+    let mut index: usize = 1;
+    while index < args.len() {
+        let option: &String = &args[index];
+        if options_ended || !option.starts_with('-') {
+            positional_args.push(option.to_owned());
+        } else if option == "--" {
+            options_ended = true;
+        } else if option == "-h" || option == "--help" {
             println!("{}", gen_help_string());
             exit(0);
         } else if option == "-V" || option == "--version" {
             println!("{} {}", PACKAGE_NAME, PACKAGE_VERSION);
             exit(0);
         } else if option == "-init-bash" {
-            println!("{}", include_str!("../bin/init.bash"));
+            println!(
+                "{}",
+                get_init_script(include_str!("../bin/init.bash"), false)
+            );
+            exit(0);
+        } else if option == "-init-zsh" {
+            println!(
+                "{}",
+                get_init_script(include_str!("../bin/init.zsh"), false)
+            );
+            exit(0);
+        } else if option == "-init-fish" {
+            println!(
+                "{}",
+                get_init_script(include_str!("../bin/init.fish"), true)
+            );
             exit(0);
+        } else if option == "-q" || option == "--query" {
+            index += 1;
+            match args.get(index) {
+                Some(query) => setting.query = Some(query.to_owned()),
+                None => unknown_argument_exit(option),
+            }
+        } else if let Some(query) = option.strip_prefix("--query=") {
+            setting.query = Some(query.to_owned());
+        } else if option == "-p" || option == "--print" {
+            setting.output = Some(Output::Stdout);
+        } else if option == "-o" || option == "--output" {
+            index += 1;
+            match args.get(index) {
+                Some(output) => setting.output = Some(Output::parse(output)),
+                None => unknown_argument_exit(option),
+            }
+        } else if let Some(output) = option.strip_prefix("--output=") {
+            setting.output = Some(Output::parse(output));
+        } else if option == "--format" {
+            index += 1;
+            match args.get(index) {
+                Some(value) => format = Some(value.to_owned()),
+                None => unknown_argument_exit(option),
+            }
+        } else if let Some(value) = option.strip_prefix("--format=") {
+            format = Some(value.to_owned());
         } else {
+            unknown_argument_exit(option);
+        }
+        index += 1;
+    }
+
+    if let Some(format) = &format {
+        match OutputFormat::parse(format) {
+            Ok(format) => setting.format = format,
+            Err(e) => {
+                eprintln!(
+                    "{}: Invalid format '{}' [{}]",
+                    "error".red().bold(),
+                    format.yellow(),
+                    e
+                );
+                exit(1);
+            }
+        }
+    }
+
+    if positional_args.len() > 0 {
+        match parse_subcommand(&positional_args) {
+            Ok(subcommand) => setting.subcommand = Some(subcommand),
+            Err(e) => {
+                eprintln!("{}: {}\n", "error".red().bold(), e);
+                println!("{}", gen_help_string());
+                exit(1);
+            }
+        }
+    }
+
+    let is_listing: bool = match &setting.subcommand {
+        Some(subcommand) => subcommand.is_listing(),
+        None => false,
+    };
+    if format.is_some() && !is_listing {
+        eprintln!(
+            "{}: '{}' only applies to tags, list and search\n",
+            "error".red().bold(),
+            "--format".yellow()
+        );
+        println!("{}", gen_help_string());
+        exit(1);
+    }
+
+    if let Some(query) = &setting.query {
+        if let Err(e) = Query::parse(query) {
             eprintln!(
-                "{}: Unknown argument '{}'\n",
+                "{}: Invalid query '{}' [{}]",
                 "error".red().bold(),
-                option.yellow()
+                query.yellow(),
+                e
             );
-            println!("{}", gen_help_string());
             exit(1);
         }
     }
+    return setting;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_output_test() {
+        assert_eq!(Output::parse("1"), Output::Stdout);
+        assert_eq!(Output::parse("3"), Output::Fd(3));
+        assert_eq!(
+            Output::parse("/tmp/command"),
+            Output::File(PathBuf::from("/tmp/command"))
+        );
+        assert_eq!(Output::parse("-1"), Output::File(PathBuf::from("-1")));
+    }
+
+    #[test]
+    fn parse_subcommand_test() {
+        let parse = |args: &[&str]| -> Result<Subcommand, String> {
+            let args: Vec<String> = args.iter().map(|arg: &&str| arg.to_string()).collect();
+            return parse_subcommand(&args);
+        };
+        assert_eq!(parse(&["tags"]), Ok(Subcommand::Tags));
+        assert_eq!(parse(&["list"]), Ok(Subcommand::List(None)));
+        assert_eq!(
+            parse(&["list", "#docker"]),
+            Ok(Subcommand::List(Some("#docker".to_owned())))
+        );
+        assert_eq!(
+            parse(&["tag", "ls -a", "#file", "#env=dev"]),
+            Ok(Subcommand::Tag(
+                "ls -a".to_owned(),
+                vec!["#file".to_owned(), "#env=dev".to_owned()]
+            ))
+        );
+        assert_eq!(
+            parse(&["untag", "ls -a", "#file"]),
+            Ok(Subcommand::Untag(
+                "ls -a".to_owned(),
+                vec!["#file".to_owned()]
+            ))
+        );
+        assert_eq!(
+            parse(&["rename-tag", "#dkr", "#docker"]),
+            Ok(Subcommand::RenameTag(
+                "#dkr".to_owned(),
+                "#docker".to_owned()
+            ))
+        );
+        assert_eq!(
+            parse(&["search", "#a & !#b"]),
+            Ok(Subcommand::Search("#a & !#b".to_owned()))
+        );
+
+        assert!(parse(&["tag", "ls -a"]).is_err());
+        assert!(parse(&["tag", "ls -a", "file"]).is_err());
+        assert!(parse(&["list", "#a #b"]).is_err());
+        assert!(parse(&["search", "#a &"]).is_err());
+        assert!(parse(&["tagz"]).is_err());
+
+        assert!(parse(&["search", "#a"]).unwrap().is_listing());
+        assert!(!parse(&["untag", "ls -a", "#file"]).unwrap().is_listing());
+        assert!(!parse(&["rename-tag", "#dkr", "#docker"])
+            .unwrap()
+            .is_listing());
+    }
 }
@@ -0,0 +1,235 @@
+use crate::config::HistoryConfig;
+use crate::edit_history::add_hashtags;
+use crate::edit_history::detach_hashtags;
+use crate::edit_history::remove_hashtag;
+use crate::edit_history::rename_hashtag;
+use crate::hashtag_tree::get_child_hashtags;
+use crate::hashtag_tree::get_hashtag_command_count;
+use crate::hashtag_tree::get_hashtag_commands;
+use crate::hashtag_tree::get_hashtag_tree;
+use crate::listing::format_records;
+use crate::listing::CommandRecord;
+use crate::listing::HashtagRecord;
+use crate::listing::OutputFormat;
+use crate::normalize::HashtagNormalizer;
+use crate::parse_history::HistoryEntry;
+use crate::parse_history::ALL_HASHTAG;
+use crate::query::Query;
+use crate::setting::Subcommand;
+use linked_hash_map::LinkedHashMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::io::Error;
+use std::io::ErrorKind;
+
+// The hashtag tree of the Hashtag View, with the commands of each parent counted in.
+pub fn get_hashtag_counts(
+    history_map: &LinkedHashMap<String, Vec<HistoryEntry>>,
+) -> Vec<(String, usize)> {
+    let hashtag_names: Vec<&str> = history_map
+        .keys()
+        .filter(|hashtag: &&String| *hashtag != ALL_HASHTAG)
+        .map(|hashtag: &String| hashtag.as_str())
+        .collect();
+    let child_hashtags: HashMap<String, HashSet<String>> = get_child_hashtags(history_map);
+    return get_hashtag_tree(&hashtag_names)
+        .into_iter()
+        .map(|hashtag_name: String| {
+            let item_count: usize =
+                get_hashtag_command_count(history_map, &child_hashtags, &hashtag_name);
+            (hashtag_name, item_count)
+        })
+        .collect();
+}
+
+// The commands of the hashtag as the Command View lists them, or of ALL without one.
+pub fn get_listed_commands(
+    history_map: &LinkedHashMap<String, Vec<HistoryEntry>>,
+    hashtag_name: Option<&str>,
+    normalizer: &HashtagNormalizer,
+) -> Result<Vec<HistoryEntry>, Error> {
+    let hashtag_name: String = match hashtag_name {
+        Some(hashtag_name) => normalizer.normalize(hashtag_name),
+        None => ALL_HASHTAG.to_owned(),
+    };
+    let child_hashtags: HashMap<String, HashSet<String>> = get_child_hashtags(history_map);
+    let commands: Vec<HistoryEntry> =
+        get_hashtag_commands(history_map, &child_hashtags, &hashtag_name);
+    if commands.len() == 0 && hashtag_name != ALL_HASHTAG {
+        return Err(unknown_hashtag_error(&hashtag_name));
+    }
+    return Ok(commands);
+}
+
+fn unknown_hashtag_error(hashtag_name: &str) -> Error {
+    return Error::new(
+        ErrorKind::NotFound,
+        format!("'{}' is not in the history", hashtag_name),
+    );
+}
+
+// A command is matched as it is listed, so `list` output can be passed back as is.
+fn get_command<'a>(
+    history_map: &'a LinkedHashMap<String, Vec<HistoryEntry>>,
+    command: &str,
+) -> Result<&'a HistoryEntry, Error> {
+    let entry: Option<&HistoryEntry> = match history_map.get(ALL_HASHTAG) {
+        Some(all) => all
+            .iter()
+            .find(|entry: &&HistoryEntry| entry.command == command),
+        None => None,
+    };
+    match entry {
+        Some(entry) => return Ok(entry),
+        None => {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("'{}' is not in the history", command),
+            ))
+        }
+    }
+}
+
+pub fn run_subcommand(
+    subcommand: &Subcommand,
+    history_map: &LinkedHashMap<String, Vec<HistoryEntry>>,
+    normalizer: &HashtagNormalizer,
+    history_config: &HistoryConfig,
+    format: OutputFormat,
+) -> Result<(), Error> {
+    let get_command_records = |entries: Vec<HistoryEntry>| -> Vec<CommandRecord> {
+        return entries
+            .iter()
+            .map(|entry: &HistoryEntry| CommandRecord::new(entry, normalizer))
+            .collect();
+    };
+    match subcommand {
+        Subcommand::Tags => {
+            let records: Vec<HashtagRecord> = get_hashtag_counts(history_map)
+                .into_iter()
+                .map(|(hashtag, count)| HashtagRecord { hashtag, count })
+                .collect();
+            print!("{}", format_records(&records, format)?);
+        }
+        Subcommand::List(hashtag_name) => {
+            let entries: Vec<HistoryEntry> =
+                get_listed_commands(history_map, hashtag_name.as_deref(), normalizer)?;
+            print!("{}", format_records(&get_command_records(entries), format)?);
+        }
+        Subcommand::Tag(command, hashtag_names) => {
+            get_command(history_map, command)?;
+            add_hashtags(command, hashtag_names, normalizer, history_config)?;
+        }
+        Subcommand::Untag(command, hashtag_names) => {
+            get_command(history_map, command)?;
+            let hashtag_names: Vec<String> = hashtag_names
+                .iter()
+                .map(|hashtag_name: &String| normalizer.normalize(hashtag_name))
+                .collect();
+            // `remove_hashtag` leaves the command as is without the hashtag
+            if hashtag_names.iter().all(|hashtag_name: &String| {
+                remove_hashtag(command, hashtag_name, normalizer) == *command
+            }) {
+                return Err(unknown_hashtag_error(&hashtag_names.join(" ")));
+            }
+            detach_hashtags(command, &hashtag_names, normalizer, history_config)?;
+        }
+        Subcommand::RenameTag(old_hashtag, new_hashtag) => {
+            let old_hashtag: String = normalizer.normalize(old_hashtag);
+            if !history_map.contains_key(&old_hashtag) {
+                return Err(unknown_hashtag_error(&old_hashtag));
+            }
+            rename_hashtag(&old_hashtag, new_hashtag, normalizer, history_config)?;
+        }
+        Subcommand::Search(query) => {
+            let query: Query =
+                Query::parse(query).map_err(|e: String| Error::new(ErrorKind::InvalidInput, e))?;
+            let entries: Vec<HistoryEntry> = query.filter_commands(history_map, normalizer);
+            print!("{}", format_records(&get_command_records(entries), format)?);
+        }
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_history::CommandIndex;
+
+    fn get_history_map(commands: &[&str]) -> LinkedHashMap<String, Vec<HistoryEntry>> {
+        let mut command_index: CommandIndex = CommandIndex::default();
+        command_index.extend(
+            commands
+                .iter()
+                .map(|s: &&str| HistoryEntry::new(s))
+                .collect(),
+        );
+        return command_index.to_command_hashmap(None, &[]);
+    }
+
+    #[test]
+    fn get_hashtag_counts_test() {
+        let history_map = get_history_map(&[
+            "ls #file",
+            "aws s3 ls #aws/s3",
+            "aws ec2 describe-instances #aws/ec2 #env=prod",
+            "make",
+        ]);
+        assert_eq!(
+            get_hashtag_counts(&history_map),
+            vec![
+                ("#aws".to_owned(), 2),
+                ("#aws/ec2".to_owned(), 1),
+                ("#aws/s3".to_owned(), 1),
+                ("#env".to_owned(), 1),
+                ("#env=prod".to_owned(), 1),
+                ("#file".to_owned(), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn get_listed_commands_test() {
+        let history_map = get_history_map(&["ls #File", "aws s3 ls #aws/s3", "make"]);
+        let normalizer: HashtagNormalizer = HashtagNormalizer::default();
+        let list = |hashtag_name: Option<&str>| -> Result<Vec<String>, Error> {
+            return Ok(
+                get_listed_commands(&history_map, hashtag_name, &normalizer)?
+                    .into_iter()
+                    .map(|entry: HistoryEntry| entry.command)
+                    .collect(),
+            );
+        };
+        assert_eq!(
+            list(None).unwrap(),
+            ["ls #File", "aws s3 ls #aws/s3", "make"]
+        );
+        assert_eq!(list(Some("#FILE")).unwrap(), ["ls #File"]);
+        assert_eq!(list(Some("#aws")).unwrap(), ["aws s3 ls #aws/s3"]);
+        assert_eq!(list(Some("#k8s")).unwrap_err().kind(), ErrorKind::NotFound);
+
+        assert!(get_command(&history_map, "make").is_ok());
+        assert!(get_command(&history_map, "ls").is_err());
+    }
+
+    #[test]
+    fn untag_unknown_hashtag_test() {
+        let history_map = get_history_map(&["ls #file", "make"]);
+        let normalizer: HashtagNormalizer = HashtagNormalizer::default();
+        // fails before the history is read, so no file is touched
+        let untag = Subcommand::Untag(
+            "ls #file".to_owned(),
+            vec!["#k8s".to_owned(), "#debug".to_owned()],
+        );
+        let e: Error = run_subcommand(
+            &untag,
+            &history_map,
+            &normalizer,
+            &HistoryConfig::default(),
+            OutputFormat::Text,
+        )
+        .unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::NotFound);
+        assert_eq!(e.to_string(), "'#k8s #debug' is not in the history");
+    }
+}
@@ -1,6 +1,36 @@
+use crate::config::add_hashtag_alias;
+use crate::config::get_config;
+use crate::config::Config;
+use crate::config::RunConfig;
+use crate::config::SortOrder;
+use crate::edit_history::add_hashtags;
+use crate::edit_history::delete_hashtag;
+use crate::edit_history::detach_hashtags;
+use crate::edit_history::parse_hashtag_input;
+use crate::edit_history::rename_hashtag;
+use crate::edit_history::strip_hashtags;
+use crate::fuzzy::fuzzy_match;
+use crate::fuzzy::FuzzyMatch;
 use crate::hashtag::Hashtag;
 use crate::hashtag::HashtagParser;
-use colored::*;
+use crate::hashtag_tree::get_child_hashtags;
+use crate::hashtag_tree::get_hashtag_command_count;
+use crate::hashtag_tree::get_hashtag_commands;
+use crate::hashtag_tree::get_hashtag_depth;
+use crate::hashtag_tree::get_hashtag_tree;
+use crate::hashtag_tree::get_parent_hashtag;
+use crate::here::HereDir;
+use crate::index_cache::get_command_index;
+use crate::keymap::Action;
+use crate::keymap::KeyPress;
+use crate::keymap::Keymap;
+use crate::normalize::HashtagNormalizer;
+use crate::parse_history::get_tidy_dir_path;
+use crate::parse_history::HistoryEntry;
+use crate::parse_history::ALL_HASHTAG;
+use crate::parse_history::HERE_HASHTAG;
+use crate::query::Query;
+use crate::setting::Output;
 use crossterm::{
     event::{self, Event, KeyCode},
     execute,
@@ -8,11 +38,16 @@ use crossterm::{
 };
 use dirs::home_dir;
 use linked_hash_map::LinkedHashMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
 use std::fs::File;
 use std::io::stdout;
+use std::io::Error;
 use std::io::Stdout;
 use std::io::Write;
 use std::iter::Map;
+use std::mem::ManuallyDrop;
+use std::os::unix::io::FromRawFd;
 use std::path::PathBuf;
 use std::slice::Iter;
 use textwrap::word_separators::*;
@@ -20,14 +55,17 @@ use textwrap::word_splitters::*;
 use textwrap::Options;
 use tui::{
     backend::{Backend, TermionBackend},
-    layout::{Constraint, Direction, Layout, Rect},
+    layout::{Constraint, Direction, Layout},
     style::{Color, Modifier, Style},
-    text::{Span, Spans},
-    widgets::{Block, Borders, Cell, Clear, Paragraph, Row, Table, TableState},
+    text::{Span, Spans, Text},
+    widgets::{Cell, Paragraph, Row, Table, TableState},
     Frame, Terminal,
 };
 
 use crate::utils::error_exit;
+use crate::utils::format_relative_time;
+use crate::utils::get_frecency;
+use crate::utils::get_unix_time;
 use unicode_width::UnicodeWidthStr;
 
 const SELECT_HASHTAG_TITLE: &'static str = "Hashtag View ";
@@ -35,8 +73,21 @@ const SELECT_COMMAND_TITLE: &'static str = "Command View ";
 const HASHTAG_VIEW_ID: u8 = 1;
 const ALL_COMMAND_VIEW_ID: u8 = 2;
 const HASHTAG_COMMAND_VIEW_ID: u8 = 3;
+const QUERY_COMMAND_VIEW_ID: u8 = 4;
 
-const ALL_HASHTAG: &'static str = "ALL";
+const HASHTAG_EDIT_ID: u8 = 1;
+const COMMAND_EDIT_ID: u8 = 2;
+const ADD_HASHTAG_EDIT_ID: u8 = 3;
+const QUERY_EDIT_ID: u8 = 4;
+const MERGE_EDIT_ID: u8 = 5;
+
+const FRECENCY_SORT_ID: u8 = 1;
+const RECENCY_SORT_ID: u8 = 2;
+const FREQUENCY_SORT_ID: u8 = 3;
+const ALPHABETICAL_SORT_ID: u8 = 4;
+
+const LAST_USED_HEADER: &'static str = "Last used";
+const LAST_USED_WIDTH: u16 = 10;
 
 const WRAP_TABLE_TEXT: &str = "table";
 const WRAP_EDITOR_TEXT: &str = "editor";
@@ -51,12 +102,17 @@ pub fn reset() {
     }
 }
 
-pub fn init_ui(map: linked_hash_map::LinkedHashMap<String, Vec<String>>) {
+pub fn init_ui(
+    map: linked_hash_map::LinkedHashMap<String, Vec<HistoryEntry>>,
+    query: Option<String>,
+    normalizer: HashtagNormalizer,
+    config: Config,
+) -> String {
     match enable_raw_mode() {
         Ok(_) => {}
         Err(err) => {
             error_exit("Failed to initialize ui", err, 1);
-            return;
+            return String::new();
         }
     }
     let mut stdout: Stdout = stdout();
@@ -65,7 +121,7 @@ pub fn init_ui(map: linked_hash_map::LinkedHashMap<String, Vec<String>>) {
         Err(err) => {
             reset();
             error_exit("Failed to enter alternate screen", err, 1);
-            return;
+            return String::new();
         }
     }
 
@@ -77,62 +133,114 @@ pub fn init_ui(map: linked_hash_map::LinkedHashMap<String, Vec<String>>) {
         Err(err) => {
             reset();
             error_exit("Failed to create new terminal", err, 1);
-            return;
+            return String::new();
         }
     };
 
-    let mut app: App = App::new(map);
+    let mut app: App = App::new(map, normalizer, config);
     app.state.select(Some(0));
+    if let Some(query) = query {
+        if let Err(e) = app.open_query(&query) {
+            app.error_message = format!("invalid query [{}]", e);
+        }
+    }
     let res: String = run_app(&mut terminal, app);
 
     match disable_raw_mode() {
         Ok(_) => {}
         Err(e) => {
             error_exit("Failed to disable raw mode", e, 1);
-            return;
+            return String::new();
         }
     }
     match execute!(terminal.backend_mut(), LeaveAlternateScreen) {
         Ok(_) => {}
         Err(e) => {
             error_exit("Failed to leave alternate screen", e, 1);
-            return;
+            return String::new();
         }
     }
     match terminal.show_cursor() {
         Ok(_) => {}
         Err(e) => {
             error_exit("Failed to show cursor", e, 1);
-            return;
+            return String::new();
         }
     }
 
-    let script_path: PathBuf = match home_dir() {
-        Some(mut history_file_path) => {
-            history_file_path.push(".history-tidy");
-            history_file_path.push("script");
-            history_file_path
-        }
-        None => {
+    return res;
+}
+
+// Hands the picked command to the shell hook, which runs it at the next prompt.
+pub fn write_script_files(command: &str, run_config: &RunConfig) {
+    // the shell runs the command without its hashtags, and adds it back to the history with or
+    // without them
+    let exec_command: String = strip_hashtags(command);
+    let history_command: String = if run_config.keep_hashtags {
+        command.to_owned()
+    } else {
+        exec_command.to_owned()
+    };
+    let confirm: String = if run_config.confirm { "yes" } else { "no" }.to_owned();
+    let tidy_dir_path: PathBuf = match get_tidy_dir_path() {
+        Ok(tidy_dir_path) => tidy_dir_path,
+        Err(e) => {
+            reset();
+            error_exit("Failed to find the script files", e, 1);
             return;
         }
     };
-
-    match File::create(script_path) {
-        Ok(mut file) => match file.write_all(res.as_bytes()) {
-            Ok(_) => {}
+    // `script` goes last, the hook reads the others once it sees a command there
+    for (file_name, content) in [
+        ("script_history", &history_command),
+        ("script_confirm", &confirm),
+        ("script", &exec_command),
+    ] {
+        match File::create(tidy_dir_path.join(file_name)) {
+            Ok(mut file) => match file.write_all(content.as_bytes()) {
+                Ok(_) => {}
+                Err(e) => {
+                    reset();
+                    error_exit("Failed to write script file", e, 1);
+                    return;
+                }
+            },
             Err(e) => {
                 reset();
-                error_exit("Failed to write script file", e, 1);
+                error_exit("Failed to create script file", e, 1);
                 return;
             }
-        },
-        Err(e) => {
-            reset();
-            error_exit("Failed to create script file", e, 1);
-            return;
-        }
+        };
+    }
+}
+
+// Hands the picked command to the caller. The shell adds it to the history as it is once it
+// runs, so the hashtags are kept or stripped here, as `write_script_files` does.
+pub fn write_output(output: &Output, command: &str, run_config: &RunConfig) -> Result<(), Error> {
+    let output_command: String = if run_config.keep_hashtags {
+        command.to_owned()
+    } else {
+        strip_hashtags(command)
     };
+    let line: String = format!("{}\n", output_command);
+    match output {
+        Output::Stdout => {
+            let mut stdout: Stdout = stdout();
+            stdout.write_all(line.as_bytes())?;
+            return stdout.flush();
+        }
+        Output::Fd(fd) => {
+            // the descriptor belongs to the caller, so it is not closed here
+            let mut file: ManuallyDrop<File> = ManuallyDrop::new(unsafe { File::from_raw_fd(*fd) });
+            return file.write_all(line.as_bytes());
+        }
+        Output::File(path) => return File::create(path)?.write_all(line.as_bytes()),
+    }
+}
+
+enum ConfirmAction {
+    DeleteHashtag(String),
+    DetachHashtag(String, String),
 }
 
 struct App {
@@ -140,49 +248,348 @@ struct App {
     table_title: &'static str,
     hashtags: Vec<Vec<String>>,
     hashtags_memo: Vec<Vec<String>>,
-    history_map: LinkedHashMap<String, Vec<String>>,
+    history_map: LinkedHashMap<String, Vec<HistoryEntry>>,
+    // the children of each parent hashtag, built with history_map
+    child_hashtags: HashMap<String, HashSet<String>>,
     header_cells: Vec<String>,
     select_hashtag_header: Vec<String>,
     view_id: u8,
     input: String,
     edit_mode: bool,
+    edit_id: u8,
     scroll: u16,
     error_message: String,
+    confirm_action: Option<ConfirmAction>,
+    search_mode: bool,
+    search_query: String,
+    search_rows: Vec<Vec<String>>,
+    search_matches: Vec<Vec<usize>>,
+    sort_id: u8,
+    // parents in the hashtag tree, and those showing their children
+    parent_hashtags: HashSet<String>,
+    expanded_hashtags: HashSet<String>,
+    normalizer: HashtagNormalizer,
+    config: Config,
+    keymap: Keymap,
+    // the start of a key sequence like "gg"
+    pending_keys: Vec<KeyPress>,
+    // rows moved by page_down and page_up, half of the table as last drawn
+    page_size: usize,
 }
 
 impl App {
-    fn new(history_map: LinkedHashMap<String, Vec<String>>) -> App {
-        let mut hashtags: Vec<Vec<String>> = vec![];
-        let mut all_hashtag: Vec<String> = vec![];
-        for hashtag in (&history_map).keys() {
-            let item_count: usize = history_map.get(hashtag).unwrap().len();
-            if hashtag == ALL_HASHTAG {
-                all_hashtag = vec![hashtag.to_owned(), item_count.to_string()];
-            } else {
-                hashtags.push(vec![hashtag.to_owned(), item_count.to_string()]);
-            }
-        }
-
-        hashtags.sort();
-        hashtags.insert(0, all_hashtag);
-
-        let hashtags_memo: Vec<Vec<String>> = hashtags.clone();
+    fn new(
+        history_map: LinkedHashMap<String, Vec<HistoryEntry>>,
+        normalizer: HashtagNormalizer,
+        config: Config,
+    ) -> App {
+        let child_hashtags: HashMap<String, HashSet<String>> = get_child_hashtags(&history_map);
+        let hashtags_memo: Vec<Vec<String>> = get_hashtag_rows(&history_map, &child_hashtags);
+        let parent_hashtags: HashSet<String> = get_parent_hashtags(&hashtags_memo);
 
         let select_hashtag_header: Vec<String> = vec!["HashTag".to_owned(), "Count".to_owned()];
 
-        App {
+        let mut app: App = App {
             state: TableState::default(),
             table_title: SELECT_HASHTAG_TITLE,
-            hashtags,
-            hashtags_memo: hashtags_memo,
+            hashtags: vec![],
+            hashtags_memo,
             history_map,
+            child_hashtags,
             header_cells: select_hashtag_header.to_owned(),
             select_hashtag_header: select_hashtag_header.to_owned(),
             view_id: HASHTAG_VIEW_ID,
             input: String::new(),
             edit_mode: false,
+            edit_id: HASHTAG_EDIT_ID,
             scroll: 0,
             error_message: String::new(),
+            confirm_action: None,
+            search_mode: false,
+            search_query: String::new(),
+            search_rows: vec![],
+            search_matches: vec![],
+            sort_id: get_sort_id(config.ui.default_sort),
+            parent_hashtags,
+            expanded_hashtags: HashSet::new(),
+            normalizer,
+            keymap: config.keys.get_keymap(),
+            config,
+            pending_keys: vec![],
+            page_size: 1,
+        };
+        app.hashtags = app.get_visible_hashtag_rows();
+        return app;
+    }
+
+    // Rows of the Hashtag View, leaving out the children of collapsed parents.
+    fn get_visible_hashtag_rows(&self) -> Vec<Vec<String>> {
+        return self
+            .hashtags_memo
+            .iter()
+            .filter(|row: &&Vec<String>| {
+                let mut hashtag_name: &str = &row[0];
+                while let Some(parent_name) = get_parent_hashtag(hashtag_name) {
+                    if !self.expanded_hashtags.contains(parent_name) {
+                        return false;
+                    }
+                    hashtag_name = parent_name;
+                }
+                return true;
+            })
+            .cloned()
+            .collect();
+    }
+
+    fn toggle_hashtag(&mut self, hashtag_name: &str) {
+        if !self.expanded_hashtags.remove(hashtag_name) {
+            self.expanded_hashtags.insert(hashtag_name.to_owned());
+        }
+        self.hashtags = self.get_visible_hashtag_rows();
+        self.select_hashtag(hashtag_name);
+    }
+
+    // Hashtags that only exist as parents of other hashtags can't be renamed or deleted.
+    fn is_editable_hashtag(&self, hashtag_name: &str) -> bool {
+        return !is_pseudo_hashtag(hashtag_name) && self.history_map.contains_key(hashtag_name);
+    }
+
+    fn reload_history_map(&mut self) -> Result<(), std::io::Error> {
+        self.history_map = get_command_index(&self.config.history, &self.normalizer)?
+            .to_command_hashmap(HereDir::current().as_ref(), &self.config.history.ignore);
+        self.child_hashtags = get_child_hashtags(&self.history_map);
+        self.hashtags_memo = get_hashtag_rows(&self.history_map, &self.child_hashtags);
+        self.parent_hashtags = get_parent_hashtags(&self.hashtags_memo);
+
+        let selected_index: usize = self.state.selected().unwrap_or(0);
+        if self.table_title == SELECT_HASHTAG_TITLE {
+            self.hashtags = self.get_visible_hashtag_rows();
+        } else if self.view_id == QUERY_COMMAND_VIEW_ID {
+            let query: String = self.header_cells[0].to_owned();
+            if self.open_query(&query).is_err() {
+                self.close_hashtag();
+            }
+        } else {
+            let hashtag_name: String = self.header_cells[0].to_owned();
+            if get_hashtag_command_count(&self.history_map, &self.child_hashtags, &hashtag_name) > 0
+            {
+                self.open_hashtag(&hashtag_name);
+            } else {
+                self.close_hashtag();
+            }
+        }
+        if self.hashtags.len() == 0 {
+            self.state.select(None);
+        } else {
+            self.state
+                .select(Some(selected_index.min(self.hashtags.len() - 1)));
+        }
+        return Ok(());
+    }
+
+    // Ties keep the most recent command first.
+    fn get_command_rows(&self, entries: &[HistoryEntry], sort_id: u8) -> Vec<Vec<String>> {
+        let now: i64 = get_unix_time();
+        let mut entries: Vec<&HistoryEntry> = entries.iter().rev().collect();
+        if sort_id == FRECENCY_SORT_ID {
+            entries.sort_by_key(|entry: &&HistoryEntry| {
+                std::cmp::Reverse(get_frecency(entry.run_count, entry.timestamp, now))
+            });
+        } else if sort_id == FREQUENCY_SORT_ID {
+            entries.sort_by_key(|entry: &&HistoryEntry| std::cmp::Reverse(entry.run_count));
+        } else if sort_id == ALPHABETICAL_SORT_ID {
+            entries.sort_by(|a: &&HistoryEntry, b: &&HistoryEntry| a.command.cmp(&b.command));
+        }
+        return entries
+            .into_iter()
+            .map(|entry: &HistoryEntry| {
+                let last_used: String = match entry.timestamp {
+                    Some(timestamp) => format_relative_time(timestamp, now),
+                    None => "-".to_owned(),
+                };
+                vec![entry.command.to_owned(), last_used]
+            })
+            .collect();
+    }
+
+    fn open_query(&mut self, query_text: &str) -> Result<(), String> {
+        let query: Query = Query::parse(query_text)?;
+        let commands: Vec<HistoryEntry> =
+            query.filter_commands(&self.history_map, &self.normalizer);
+
+        self.header_cells = vec![query_text.to_owned(), LAST_USED_HEADER.to_owned()];
+        self.view_id = QUERY_COMMAND_VIEW_ID;
+        self.hashtags = self.get_command_rows(&commands, self.sort_id);
+        if self.hashtags.len() == 0 {
+            self.state.select(None);
+        } else {
+            self.state.select(Some(0));
+        }
+        self.table_title = SELECT_COMMAND_TITLE;
+        return Ok(());
+    }
+
+    // Makes `hashtag_name` an alias of `target_name`, which the history is grouped by from now on.
+    fn merge_hashtag(&mut self, hashtag_name: &str, target_name: &str) -> Result<(), Error> {
+        add_hashtag_alias(hashtag_name, target_name)?;
+        self.normalizer = HashtagNormalizer::new(&get_config()?.hashtag);
+        return self.reload_history_map();
+    }
+
+    fn open_hashtag(&mut self, hashtag_name: &str) {
+        // a parent lists the commands of its children as well
+        let history_group: Vec<HistoryEntry> =
+            get_hashtag_commands(&self.history_map, &self.child_hashtags, hashtag_name);
+
+        self.hashtags = self.get_command_rows(&history_group, self.get_group_sort_id(hashtag_name));
+        self.header_cells = vec![hashtag_name.to_owned(), LAST_USED_HEADER.to_owned()];
+        if is_pseudo_hashtag(hashtag_name) {
+            self.view_id = ALL_COMMAND_VIEW_ID;
+        } else {
+            self.view_id = HASHTAG_COMMAND_VIEW_ID;
+        }
+        self.state.select(Some(0));
+        self.table_title = SELECT_COMMAND_TITLE;
+    }
+
+    // Here is ranked by the last run in this directory, which its entries are in the order of,
+    // whatever the sort.
+    fn get_group_sort_id(&self, hashtag_name: &str) -> u8 {
+        if hashtag_name == HERE_HASHTAG {
+            return RECENCY_SORT_ID;
+        }
+        return self.sort_id;
+    }
+
+    fn cycle_sort(&mut self) {
+        self.sort_id = if self.sort_id == ALPHABETICAL_SORT_ID {
+            FRECENCY_SORT_ID
+        } else {
+            self.sort_id + 1
+        };
+        let header: String = self.header_cells[0].to_owned();
+        if self.view_id == QUERY_COMMAND_VIEW_ID {
+            if self.open_query(&header).is_err() {
+                self.close_hashtag();
+            }
+        } else {
+            self.open_hashtag(&header);
+        }
+    }
+
+    fn close_hashtag(&mut self) {
+        self.hashtags = self.get_visible_hashtag_rows();
+        self.header_cells = self.select_hashtag_header.to_owned();
+        self.state.select(Some(0));
+        self.table_title = SELECT_HASHTAG_TITLE;
+        self.view_id = HASHTAG_VIEW_ID;
+    }
+
+    fn select_hashtag(&mut self, hashtag_name: &str) {
+        let index: usize = self
+            .hashtags
+            .iter()
+            .position(|item: &Vec<String>| item[0] == hashtag_name)
+            .unwrap_or(0);
+        self.state.select(Some(index));
+    }
+
+    fn select_next(&mut self) {
+        if self.hashtags.len() == 0 {
+            return;
+        }
+        let i: usize = match self.state.selected() {
+            Some(i) => {
+                if i >= self.hashtags.len() - 1 {
+                    0
+                } else {
+                    i + 1
+                }
+            }
+            None => 0,
+        };
+        self.state.select(Some(i));
+    }
+
+    fn select_previous(&mut self) {
+        if self.hashtags.len() == 0 {
+            return;
+        }
+        let i: usize = match self.state.selected() {
+            Some(i) => {
+                if i == 0 {
+                    self.hashtags.len() - 1
+                } else {
+                    i - 1
+                }
+            }
+            None => 0,
+        };
+        self.state.select(Some(i));
+    }
+
+    // Unlike select_next and select_previous, stops at the first and last rows.
+    fn select_offset(&mut self, offset: isize) {
+        if self.hashtags.len() == 0 {
+            return;
+        }
+        let i: isize = match self.state.selected() {
+            Some(i) => i as isize + offset,
+            None => 0,
+        };
+        self.state
+            .select(Some(i.clamp(0, self.hashtags.len() as isize - 1) as usize));
+    }
+
+    fn start_search(&mut self) {
+        self.search_mode = true;
+        self.search_query = String::new();
+        // the children of collapsed parents can be found too
+        self.search_rows = if self.view_id == HASHTAG_VIEW_ID {
+            self.hashtags_memo.clone()
+        } else {
+            self.hashtags.clone()
+        };
+        self.search_matches = vec![];
+    }
+
+    fn end_search(&mut self) {
+        self.search_mode = false;
+        self.search_query = String::new();
+        self.search_rows = vec![];
+        self.search_matches = vec![];
+    }
+
+    fn cancel_search(&mut self) {
+        self.hashtags = if self.view_id == HASHTAG_VIEW_ID {
+            self.get_visible_hashtag_rows()
+        } else {
+            self.search_rows.clone()
+        };
+        self.state.select(Some(0));
+        self.end_search();
+    }
+
+    fn filter_rows(&mut self) {
+        let mut matched_rows: Vec<(FuzzyMatch, &Vec<String>)> = vec![];
+        for row in &self.search_rows {
+            if let Some(fuzzy_match) = fuzzy_match(&self.search_query, &row[0]) {
+                matched_rows.push((fuzzy_match, row));
+            }
+        }
+        if self.search_query.len() > 0 {
+            matched_rows.sort_by_key(|(fuzzy_match, _)| std::cmp::Reverse(fuzzy_match.score));
+        }
+
+        self.hashtags = matched_rows.iter().map(|(_, row)| (*row).clone()).collect();
+        self.search_matches = matched_rows
+            .into_iter()
+            .map(|(fuzzy_match, _)| fuzzy_match.indices)
+            .collect();
+        if self.hashtags.len() == 0 {
+            self.state.select(None);
+        } else {
+            self.state.select(Some(0));
         }
     }
 
@@ -192,6 +599,95 @@ impl App {
             None => unreachable!(),
         }
     }
+
+    fn get_select_entry(&self) -> Option<&HistoryEntry> {
+        let index: usize = self.state.selected()?;
+        let command: &String = &self.hashtags.get(index)?[0];
+        return self
+            .history_map
+            .get(ALL_HASHTAG)?
+            .iter()
+            .find(|entry: &&HistoryEntry| &entry.command == command);
+    }
+}
+
+fn get_sort_id(sort_order: SortOrder) -> u8 {
+    match sort_order {
+        SortOrder::Frecency => return FRECENCY_SORT_ID,
+        SortOrder::Recency => return RECENCY_SORT_ID,
+        SortOrder::Frequency => return FREQUENCY_SORT_ID,
+        SortOrder::Alphabetical => return ALPHABETICAL_SORT_ID,
+    }
+}
+
+fn get_sort_name(sort_id: u8) -> &'static str {
+    match sort_id {
+        FRECENCY_SORT_ID => "frecency",
+        RECENCY_SORT_ID => "recency",
+        FREQUENCY_SORT_ID => "frequency",
+        ALPHABETICAL_SORT_ID => "alphabetical",
+        _ => unreachable!(),
+    }
+}
+
+// ALL and Here group commands by something other than a hashtag, so they can't be edited.
+fn is_pseudo_hashtag(hashtag_name: &str) -> bool {
+    return hashtag_name == ALL_HASHTAG || hashtag_name == HERE_HASHTAG;
+}
+
+// ALL and Here come first, then the hashtag tree with the commands of each parent counted in.
+fn get_hashtag_rows(
+    history_map: &LinkedHashMap<String, Vec<HistoryEntry>>,
+    child_hashtags: &HashMap<String, HashSet<String>>,
+) -> Vec<Vec<String>> {
+    let mut hashtags: Vec<Vec<String>> = vec![];
+    for pseudo_hashtag in [ALL_HASHTAG, HERE_HASHTAG] {
+        if let Some(entries) = history_map.get(pseudo_hashtag) {
+            hashtags.push(vec![pseudo_hashtag.to_owned(), entries.len().to_string()]);
+        }
+    }
+
+    let hashtag_names: Vec<&str> = history_map
+        .keys()
+        .filter(|hashtag: &&String| !is_pseudo_hashtag(hashtag))
+        .map(|hashtag: &String| hashtag.as_str())
+        .collect();
+    for hashtag_name in get_hashtag_tree(&hashtag_names) {
+        let item_count: usize =
+            get_hashtag_command_count(history_map, child_hashtags, &hashtag_name);
+        hashtags.push(vec![hashtag_name, item_count.to_string()]);
+    }
+    return hashtags;
+}
+
+// Indents a row of the hashtag tree by its depth and marks whether a parent is folded.
+fn get_tree_prefix(
+    hashtag_name: &str,
+    parent_hashtags: &HashSet<String>,
+    expanded_hashtags: &HashSet<String>,
+) -> String {
+    let marker: &str = if !parent_hashtags.contains(hashtag_name) {
+        "  "
+    } else if expanded_hashtags.contains(hashtag_name) {
+        "▾ "
+    } else {
+        "▸ "
+    };
+    return "  ".repeat(get_hashtag_depth(hashtag_name)) + marker;
+}
+
+// Whether the command itself carries the hashtag, rather than one of its children.
+fn has_hashtag(command: &str, hashtag_name: &str, normalizer: &HashtagNormalizer) -> bool {
+    return HashtagParser::new_shell(command)
+        .any(|hashtag: Hashtag| normalizer.normalize_hashtag(&hashtag) == hashtag_name);
+}
+
+fn get_parent_hashtags(hashtag_rows: &[Vec<String>]) -> HashSet<String> {
+    return hashtag_rows
+        .iter()
+        .filter_map(|row: &Vec<String>| get_parent_hashtag(&row[0]))
+        .map(|parent_name: &str| parent_name.to_owned())
+        .collect();
 }
 
 fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> String {
@@ -220,43 +716,192 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> String {
         };
         let key_code: event::KeyCode = key.code;
 
+        if let Some(confirm_action) = app.confirm_action.take() {
+            if app.keymap.get_key_action(KeyPress::from(key)) == Some(Action::Confirm) {
+                let result: Result<(), std::io::Error> = match &confirm_action {
+                    ConfirmAction::DeleteHashtag(hashtag_name) => {
+                        delete_hashtag(hashtag_name, &app.normalizer, &app.config.history)
+                    }
+                    ConfirmAction::DetachHashtag(command, hashtag_name) => detach_hashtags(
+                        command,
+                        std::slice::from_ref(hashtag_name),
+                        &app.normalizer,
+                        &app.config.history,
+                    ),
+                };
+                match result.and_then(|_| app.reload_history_map()) {
+                    Ok(_) => {
+                        app.error_message = String::new();
+                    }
+                    Err(e) => {
+                        app.error_message = format!("Failed to save hashtag [{}]", e);
+                    }
+                }
+            }
+            continue;
+        }
+
+        // keys that don't type text, like Ctrl-c or Ctrl-n, still go to the keymap in the search
+        // and edit modes
+        let key_press: KeyPress = KeyPress::from(key);
+        let text_action: Option<Action> = if key_press.is_text() {
+            None
+        } else {
+            app.keymap.get_key_action(key_press)
+        };
+
+        if app.search_mode {
+            if key_code == KeyCode::Esc {
+                app.cancel_search();
+                continue;
+            } else if key_code == KeyCode::Backspace {
+                app.search_query.pop();
+                app.filter_rows();
+                continue;
+            } else if key_press.is_text() {
+                if let KeyCode::Char(c) = key_code {
+                    app.search_query.push(c);
+                    app.filter_rows();
+                }
+                continue;
+            }
+            match text_action {
+                Some(Action::Quit) => {
+                    return "".to_owned();
+                }
+                Some(Action::MoveDown) => {
+                    app.select_next();
+                    continue;
+                }
+                Some(Action::MoveUp) => {
+                    app.select_previous();
+                    continue;
+                }
+                Some(Action::PageDown) => {
+                    app.select_offset(app.page_size as isize);
+                    continue;
+                }
+                Some(Action::PageUp) => {
+                    app.select_offset(-(app.page_size as isize));
+                    continue;
+                }
+                Some(Action::MoveTop) => {
+                    app.select_offset(isize::MIN / 2);
+                    continue;
+                }
+                Some(Action::MoveBottom) => {
+                    app.select_offset(isize::MAX / 2);
+                    continue;
+                }
+                Some(Action::Select) if app.hashtags.len() > 0 => {
+                    app.end_search();
+                }
+                _ => continue,
+            }
+        }
+
         if app.edit_mode {
             if key_code == KeyCode::Enter {
-                if app.table_title == SELECT_HASHTAG_TITLE {
+                if app.edit_id == HASHTAG_EDIT_ID {
                     let hashtags: Vec<Hashtag> =
                         HashtagParser::new(&app.input).collect::<Vec<Hashtag>>();
-                    app.error_message = format!("{:?}", hashtags);
                     if hashtags.len() != 1 {
                         app.error_message = "invalid hashtag(1)".to_owned();
                     } else if hashtags[0].end + 1 != app.input.chars().count() {
                         app.error_message = "invalid hashtag(2)".to_owned();
                     } else {
-                        // app.error_message = app.input.clone();
-                        let selected_index = app.state.selected().unwrap();
-                        let original_hashtag = app.hashtags[selected_index].clone();
-                        app.hashtags[selected_index][0] = app.input.to_owned();
-                        // app.history_map[&app.input.to_owned()] = original_hashtag.clone();
-                        app.history_map
-                            .insert(app.input.to_owned(), original_hashtag.to_owned());
-                        app.history_map.remove(&original_hashtag[0].to_owned());
-
-                        app.hashtags_memo = app.hashtags.clone();
-                        // app.hashtags_memo[app.state.selected().unwrap()][0] = app.input.to_owned();
-
-                        // app.error_message = format!("{:?}", app.hashtags);
-                        app.error_message = format!("{:?}", app.history_map);
-                        app.edit_mode = false;
+                        let original_hashtag: String = app.get_select_item()[0].to_owned();
+                        let new_hashtag: String = app.input.to_owned();
+                        match rename_hashtag(
+                            &original_hashtag,
+                            &new_hashtag,
+                            &app.normalizer,
+                            &app.config.history,
+                        )
+                        .and_then(|_| app.reload_history_map())
+                        {
+                            Ok(_) => {
+                                app.select_hashtag(&app.normalizer.normalize(&new_hashtag));
+                                app.error_message = String::new();
+                                app.edit_mode = false;
+                            }
+                            Err(e) => {
+                                app.error_message = format!("Failed to save hashtag [{}]", e);
+                            }
+                        }
+                    }
+                } else if app.edit_id == ADD_HASHTAG_EDIT_ID {
+                    let input: String = app.input.replace("\n", "");
+                    match parse_hashtag_input(&input) {
+                        Some(hashtag_names) => {
+                            let command: String = app.get_select_item()[0].to_owned();
+                            match add_hashtags(
+                                &command,
+                                &hashtag_names,
+                                &app.normalizer,
+                                &app.config.history,
+                            )
+                            .and_then(|_| app.reload_history_map())
+                            {
+                                Ok(_) => {
+                                    app.error_message = String::new();
+                                    app.edit_mode = false;
+                                }
+                                Err(e) => {
+                                    app.error_message = format!("Failed to save hashtag [{}]", e);
+                                }
+                            }
+                        }
+                        None => {
+                            app.error_message = "invalid hashtag".to_owned();
+                        }
+                    }
+                } else if app.edit_id == MERGE_EDIT_ID {
+                    let input: String = app.input.replace("\n", "");
+                    let hashtag_name: String = app.get_select_item()[0].to_owned();
+                    match parse_hashtag_input(&input) {
+                        Some(hashtag_names) if hashtag_names.len() == 1 => {
+                            let target_name: String = app.normalizer.normalize(&hashtag_names[0]);
+                            if target_name == hashtag_name {
+                                app.error_message = "can't merge a hashtag into itself".to_owned();
+                            } else {
+                                match app.merge_hashtag(&hashtag_name, &target_name) {
+                                    Ok(_) => {
+                                        app.select_hashtag(&target_name);
+                                        app.error_message = String::new();
+                                        app.edit_mode = false;
+                                    }
+                                    Err(e) => {
+                                        app.error_message = format!("Failed to save alias [{}]", e);
+                                    }
+                                }
+                            }
+                        }
+                        _ => {
+                            app.error_message = "invalid hashtag".to_owned();
+                        }
+                    }
+                } else if app.edit_id == QUERY_EDIT_ID {
+                    let input: String = app.input.replace("\n", "");
+                    match app.open_query(&input) {
+                        Ok(_) => {
+                            app.error_message = String::new();
+                            app.edit_mode = false;
+                        }
+                        Err(e) => {
+                            app.error_message = format!("invalid query [{}]", e);
+                        }
                     }
                 } else {
                     app.edit_mode = false;
                 }
             } else if key_code == KeyCode::Backspace {
                 if app.input.len() > 0 {
-                    if app.table_title == SELECT_HASHTAG_TITLE {
+                    if app.edit_id == HASHTAG_EDIT_ID {
                         if app.input.len() == 1 {
                             continue;
                         }
-                    } else {
+                    } else if app.edit_id == COMMAND_EDIT_ID {
                         app.error_message = app.input.to_owned();
                         if app.input.len() == 2 {
                             continue;
@@ -273,94 +918,166 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> String {
                 }
             } else if key_code == KeyCode::Esc {
                 app.edit_mode = false;
-            } else {
-                match key_code {
-                    KeyCode::Char(c) => {
-                        app.input.push(c);
-                    }
-                    _ => {}
+            } else if key_press.is_text() {
+                if let KeyCode::Char(c) = key_code {
+                    app.input.push(c);
                 }
+            } else if text_action == Some(Action::Quit) {
+                return "".to_owned();
             }
 
             continue;
         }
 
-        if key_code == KeyCode::Char('q') {
-            return "".to_owned();
-        } else if key_code == KeyCode::Char('e') {
-            let select_item = app.get_select_item();
-            let hashtag_name: String = select_item[0].to_owned();
-            if hashtag_name != ALL_HASHTAG {
-                if app.table_title == SELECT_HASHTAG_TITLE {
-                    app.input = select_item[0].to_owned();
-                } else {
-                    app.input = "$ ".to_owned() + &select_item[0];
-                }
-                app.edit_mode = true;
+        let action: Action = match app
+            .keymap
+            .read_key(&mut app.pending_keys, KeyPress::from(key))
+        {
+            Some(action) => action,
+            None => continue,
+        };
+
+        if app.hashtags.len() == 0 && action != Action::Quit && action != Action::Back {
+            continue;
+        }
+
+        match action {
+            Action::Quit => {
+                return "".to_owned();
             }
-        } else if key_code == KeyCode::Down {
-            let i: usize = match app.state.selected() {
-                Some(i) => {
-                    if i >= app.hashtags.len() - 1 {
-                        0
+            Action::Search => {
+                app.start_search();
+            }
+            Action::Edit => {
+                let select_item = app.get_select_item();
+                let hashtag_name: String = select_item[0].to_owned();
+                if app.table_title == SELECT_COMMAND_TITLE || app.is_editable_hashtag(&hashtag_name)
+                {
+                    if app.table_title == SELECT_HASHTAG_TITLE {
+                        app.input = select_item[0].to_owned();
+                        app.edit_id = HASHTAG_EDIT_ID;
                     } else {
-                        i + 1
+                        app.input = "$ ".to_owned() + &select_item[0];
+                        app.edit_id = COMMAND_EDIT_ID;
                     }
+                    app.edit_mode = true;
                 }
-                None => 0,
-            };
-            app.state.select(Some(i));
-        } else if key_code == KeyCode::Up {
-            let i: usize = match app.state.selected() {
-                Some(i) => {
-                    if i == 0 {
-                        app.hashtags.len() - 1
-                    } else {
-                        i - 1
-                    }
+            }
+            Action::Tag if app.table_title == SELECT_COMMAND_TITLE => {
+                app.input = "#".to_owned();
+                app.edit_id = ADD_HASHTAG_EDIT_ID;
+                app.error_message = String::new();
+                app.edit_mode = true;
+            }
+            Action::Sort if app.table_title == SELECT_COMMAND_TITLE => {
+                app.cycle_sort();
+            }
+            Action::Merge
+                if app.view_id == HASHTAG_VIEW_ID
+                    && app.is_editable_hashtag(&app.get_select_item()[0]) =>
+            {
+                app.input = "#".to_owned();
+                app.edit_id = MERGE_EDIT_ID;
+                app.error_message = String::new();
+                app.edit_mode = true;
+            }
+            Action::Query if app.table_title == SELECT_HASHTAG_TITLE => {
+                app.input = "#".to_owned();
+                app.edit_id = QUERY_EDIT_ID;
+                app.error_message = String::new();
+                app.edit_mode = true;
+            }
+            Action::Delete => {
+                let select_item: Vec<String> = app.get_select_item();
+                if app.view_id == HASHTAG_VIEW_ID && app.is_editable_hashtag(&select_item[0]) {
+                    app.confirm_action =
+                        Some(ConfirmAction::DeleteHashtag(select_item[0].to_owned()));
+                } else if app.view_id == HASHTAG_COMMAND_VIEW_ID
+                    && has_hashtag(&select_item[0], &app.header_cells[0], &app.normalizer)
+                {
+                    app.confirm_action = Some(ConfirmAction::DetachHashtag(
+                        select_item[0].to_owned(),
+                        app.header_cells[0].to_owned(),
+                    ));
                 }
-                None => 0,
-            };
-            app.state.select(Some(i));
-        } else if app.table_title == SELECT_HASHTAG_TITLE
-            && (key_code == KeyCode::Enter || key_code == KeyCode::Right)
-        {
-            let select_item: Vec<String> = app.get_select_item();
-            let hashtag_name: String = select_item[0].to_owned();
-            let history_group: &&Vec<String> = &app.history_map.get(hashtag_name.as_str()).unwrap();
-
-            let mut hashtags: Vec<Vec<String>> = vec![];
-            for history in history_group.iter() {
-                if hashtag_name == ALL_HASHTAG {
-                    hashtags.push(vec![history.to_owned()]);
+            }
+            Action::MoveDown => {
+                app.select_next();
+            }
+            Action::MoveUp => {
+                app.select_previous();
+            }
+            Action::PageDown => {
+                app.select_offset(app.page_size as isize);
+            }
+            Action::PageUp => {
+                app.select_offset(-(app.page_size as isize));
+            }
+            Action::MoveTop => {
+                app.select_offset(isize::MIN / 2);
+            }
+            Action::MoveBottom => {
+                app.select_offset(isize::MAX / 2);
+            }
+            Action::Open if app.view_id == HASHTAG_VIEW_ID => {
+                let hashtag_name: String = app.get_select_item()[0].to_owned();
+                if app.parent_hashtags.contains(&hashtag_name)
+                    && !app.expanded_hashtags.contains(&hashtag_name)
+                {
+                    app.toggle_hashtag(&hashtag_name);
                 } else {
-                    hashtags.push(vec![history.to_owned()]);
+                    app.open_hashtag(&hashtag_name);
                 }
             }
+            Action::Back if app.view_id == HASHTAG_VIEW_ID => {
+                let hashtag_name: String = app.get_select_item()[0].to_owned();
+                if app.expanded_hashtags.contains(&hashtag_name) {
+                    app.toggle_hashtag(&hashtag_name);
+                } else if let Some(parent_name) = get_parent_hashtag(&hashtag_name) {
+                    app.select_hashtag(parent_name);
+                }
+            }
+            Action::Open | Action::Select if app.table_title == SELECT_HASHTAG_TITLE => {
+                let select_item: Vec<String> = app.get_select_item();
+                app.open_hashtag(&select_item[0]);
+            }
+            Action::Select if app.table_title == SELECT_COMMAND_TITLE => {
+                let select_item: Vec<String> = app.get_select_item();
+                let select_command = select_item[0].to_owned();
+                return select_command;
+            }
+            Action::Back if app.table_title == SELECT_COMMAND_TITLE => {
+                app.close_hashtag();
+            }
+            _ => {}
+        }
+    }
+}
 
-            app.header_cells = vec![hashtag_name.clone()];
-            if hashtag_name == ALL_HASHTAG {
-                app.view_id = ALL_COMMAND_VIEW_ID;
-            } else {
-                app.view_id = HASHTAG_COMMAND_VIEW_ID;
-            }
-            hashtags.reverse();
-            app.hashtags = hashtags;
-            app.state.select(Some(0));
-            app.table_title = SELECT_COMMAND_TITLE;
-        } else if app.table_title == SELECT_COMMAND_TITLE && key_code == KeyCode::Enter {
-            let select_item: Vec<String> = app.get_select_item();
-            let select_command = select_item[0].to_owned();
-            return select_command;
-        } else if app.table_title == SELECT_COMMAND_TITLE && key_code == KeyCode::Left {
-            app.hashtags = app.hashtags_memo.clone();
-            app.header_cells = app.select_hashtag_header.to_owned();
-            app.state.select(Some(0));
-            app.table_title = SELECT_HASHTAG_TITLE;
-            app.view_id = HASHTAG_VIEW_ID;
+// e.g. "exit 0  ~/src/app  @laptop", leaving out what the history file didn't record
+fn format_entry_details(entry: &HistoryEntry) -> String {
+    let mut details: Vec<String> = vec![];
+    if let Some(exit_status) = entry.exit_status {
+        details.push(format!("exit {}", exit_status));
+    }
+    if let Some(cwd) = &entry.cwd {
+        let home: Option<String> = home_dir().map(|home| home.to_string_lossy().into_owned());
+        match home
+            .as_ref()
+            .and_then(|home: &String| cwd.strip_prefix(home.as_str()))
+        {
+            Some(rest) if rest.len() == 0 || rest.starts_with('/') => {
+                details.push("~".to_owned() + rest);
+            }
+            _ => details.push(cwd.to_owned()),
         }
     }
+    if let Some(hostname) = &entry.hostname {
+        details.push("@".to_owned() + hostname);
+    }
+    return details.join("  ");
 }
+
 fn wrap_text(text: String, limit: usize, mode: &str) -> String {
     let base_options = Options::new(limit).word_splitter(NoHyphenation);
     if mode == WRAP_TABLE_TEXT {
@@ -374,7 +1091,73 @@ fn wrap_text(text: String, limit: usize, mode: &str) -> String {
     }
 }
 
+// Restyles the characters of `content` at `indices` after it was wrapped by `wrap_text`, which only
+// removes whitespace at the wrap points and inserts new lines.
+fn highlight_text(
+    content: &str,
+    wrapped: &str,
+    indices: &[usize],
+    highlight_color: Color,
+) -> Text<'static> {
+    let highlight_style: Style = Style::default()
+        .fg(highlight_color)
+        .add_modifier(Modifier::BOLD);
+    let content_chars: Vec<char> = content.chars().collect();
+
+    let mut lines: Vec<Spans> = vec![];
+    let mut spans: Vec<Span> = vec![];
+    let mut buffer: String = String::new();
+    let mut buffer_highlighted: bool = false;
+    let mut content_index: usize = 0;
+    for wrapped_char in wrapped.chars() {
+        if wrapped_char == '\n' {
+            spans.push(Span::styled(
+                std::mem::take(&mut buffer),
+                if buffer_highlighted {
+                    highlight_style
+                } else {
+                    Style::default()
+                },
+            ));
+            lines.push(Spans::from(std::mem::take(&mut spans)));
+            continue;
+        }
+        while content_index < content_chars.len() && content_chars[content_index] != wrapped_char {
+            content_index += 1;
+        }
+
+        let highlighted: bool = indices.contains(&content_index);
+        if highlighted != buffer_highlighted && buffer.len() > 0 {
+            spans.push(Span::styled(
+                std::mem::take(&mut buffer),
+                if buffer_highlighted {
+                    highlight_style
+                } else {
+                    Style::default()
+                },
+            ));
+        }
+        buffer_highlighted = highlighted;
+        buffer.push(wrapped_char);
+        content_index += 1;
+    }
+    spans.push(Span::styled(
+        buffer,
+        if buffer_highlighted {
+            highlight_style
+        } else {
+            Style::default()
+        },
+    ));
+    lines.push(Spans::from(spans));
+    return Text::from(lines);
+}
+
 fn ui<B: Backend>(frame: &mut Frame<B>, app: &mut App) {
+    let highlight_color: Color = app.config.ui.theme.highlight.0;
+    let key_color: Color = app.config.ui.theme.key.0;
+    let details_color: Color = app.config.ui.theme.details.0;
+    let error_color: Color = app.config.ui.theme.error.0;
     let frame_size: tui::layout::Rect = frame.size();
 
     let chunks = Layout::default()
@@ -395,56 +1178,112 @@ fn ui<B: Backend>(frame: &mut Frame<B>, app: &mut App) {
     let header_cells: Map<Iter<String>, _> = app.header_cells.iter().map(|h| Cell::from(&(**h)));
     let header: tui::widgets::Row = Row::new(header_cells).height(1).style(normal_style);
 
-    let rows: Map<Iter<Vec<String>>, _> = app.hashtags.iter().map(|item| {
-        let text_margin: usize = highlight_symbol.len();
+    let is_command_view: bool = app.view_id == ALL_COMMAND_VIEW_ID
+        || app.view_id == HASHTAG_COMMAND_VIEW_ID
+        || app.view_id == QUERY_COMMAND_VIEW_ID;
+    let text_margin: usize = highlight_symbol.len();
+    let text_width: usize = if chunks[0].width as usize >= text_margin {
+        chunks[0].width as usize - text_margin
+    } else {
+        chunks[0].width as usize
+    };
+    // the columns are separated by one space
+    let command_width: usize = if text_width > LAST_USED_WIDTH as usize + 1 {
+        text_width - LAST_USED_WIDTH as usize - 1
+    } else {
+        text_width
+    };
 
-        let text_width: usize = if chunks[0].width as usize >= text_margin {
-            chunks[0].width as usize - text_margin
-        } else {
-            chunks[0].width as usize
-        };
+    let search_matches: &Vec<Vec<usize>> = &app.search_matches;
+    let rows = app.hashtags.iter().enumerate().map(|(row_index, item)| {
+        let no_indices: Vec<usize> = vec![];
+        let indices: &Vec<usize> = search_matches.get(row_index).unwrap_or(&no_indices);
 
         let mut height_count: u16 = 1;
-        if app.view_id == ALL_COMMAND_VIEW_ID || app.view_id == HASHTAG_COMMAND_VIEW_ID {
+        if is_command_view {
             // one line
-            let cells: Map<Iter<String>, _> = item.iter().map(|content: &String| {
-                let content: String = "$ ".to_owned() + content.as_str();
-                let converted_string = wrap_text(content, text_width, WRAP_TABLE_TEXT);
-                height_count = converted_string.matches("\n").count() as u16 + 1;
-                return converted_string;
-            });
+            let cells = item
+                .iter()
+                .enumerate()
+                .map(|(cell_index, content): (usize, &String)| {
+                    if cell_index != 0 {
+                        return Text::from(content.to_owned());
+                    }
+                    let content: String = "$ ".to_owned() + content.as_str();
+                    let converted_string =
+                        wrap_text(content.to_owned(), command_width, WRAP_TABLE_TEXT);
+                    height_count = converted_string.matches("\n").count() as u16 + 1;
+                    // the matched indices don't count the "$ " prompt
+                    let prompt_indices: Vec<usize> =
+                        indices.iter().map(|i: &usize| i + 2).collect();
+                    return highlight_text(
+                        &content,
+                        &converted_string,
+                        &prompt_indices,
+                        highlight_color,
+                    );
+                });
 
-            return Row::new(cells).height(height_count as u16);
+            return Row::new(cells).height(height_count);
         } else {
             // two line
-            let cells: Map<Iter<String>, _> = item.iter().map(|content: &String| {
-                let converted_string =
-                    wrap_text(content.to_owned(), text_width / 2, WRAP_TABLE_TEXT);
+            let tree_prefix: String = if app.view_id == HASHTAG_VIEW_ID {
+                get_tree_prefix(&item[0], &app.parent_hashtags, &app.expanded_hashtags)
+            } else {
+                String::new()
+            };
+            let cells = item
+                .iter()
+                .enumerate()
+                .map(|(cell_index, content): (usize, &String)| {
+                    let content: String = if cell_index == 0 {
+                        tree_prefix.to_owned() + content
+                    } else {
+                        content.to_owned()
+                    };
+                    let converted_string =
+                        wrap_text(content.to_owned(), text_width / 2, WRAP_TABLE_TEXT);
 
-                let tmp_height_count = converted_string.matches("\n").count() as u16 + 1;
-                if tmp_height_count > height_count {
-                    height_count = tmp_height_count;
-                }
+                    let tmp_height_count = converted_string.matches("\n").count() as u16 + 1;
+                    if tmp_height_count > height_count {
+                        height_count = tmp_height_count;
+                    }
 
-                return converted_string;
-            });
+                    if cell_index == 0 {
+                        let prefix_len: usize = tree_prefix.chars().count();
+                        let prefix_indices: Vec<usize> =
+                            indices.iter().map(|i: &usize| i + prefix_len).collect();
+                        return highlight_text(
+                            &content,
+                            &converted_string,
+                            &prefix_indices,
+                            highlight_color,
+                        );
+                    }
+                    return Text::from(converted_string);
+                });
 
-            return Row::new(cells).height(height_count as u16);
+            return Row::new(cells).height(height_count);
         };
     });
-    let header_cells_count: u16 = 100 / (app.header_cells.len() as u16);
-    let widths: &[tui::layout::Constraint; 2] = &[
-        Constraint::Percentage(header_cells_count),
-        Constraint::Percentage(header_cells_count),
-    ];
+    let widths: [tui::layout::Constraint; 2] = if is_command_view {
+        [
+            Constraint::Length(command_width as u16),
+            Constraint::Length(LAST_USED_WIDTH),
+        ]
+    } else {
+        [Constraint::Percentage(50), Constraint::Percentage(50)]
+    };
     let selected_style: Style = Style::default().add_modifier(Modifier::REVERSED);
     let table: tui::widgets::Table = Table::new(rows)
         .header(header)
         .highlight_style(selected_style)
         .highlight_symbol(highlight_symbol)
-        .widths(widths);
+        .widths(&widths);
 
     if app.edit_mode == false {
+        // less the borders and the header
+        app.page_size = (chunks[0].height as usize).saturating_sub(4).max(2) / 2;
         frame.render_stateful_widget(table, chunks[0], &mut app.state);
     } else {
         // ウィンドウサイズを変更しないかぎり普遍
@@ -453,7 +1292,7 @@ fn ui<B: Backend>(frame: &mut Frame<B>, app: &mut App) {
 
         let raw_input: String = app.input.replace("\n", "");
 
-        app.input = wrap_text(raw_input, chunks_width as usize, WRAP_EDITOR_TEXT);
+        app.input = wrap_text(raw_input, chunks_width, WRAP_EDITOR_TEXT);
 
         let return_count: usize = app.input.matches("\n").count();
         let last_line_width: usize = app.input.split("\n").last().unwrap().width();
@@ -487,61 +1326,321 @@ fn ui<B: Backend>(frame: &mut Frame<B>, app: &mut App) {
         frame.set_cursor(cursor_x, cursor_y)
     }
 
-    let help_text: Vec<tui::text::Spans> = if app.edit_mode {
+    let help_text: Vec<tui::text::Spans> = if let Some(confirm_action) = &app.confirm_action {
+        let confirm_message: String = match confirm_action {
+            ConfirmAction::DeleteHashtag(hashtag_name) => {
+                format!("Delete {} from all commands?", hashtag_name)
+            }
+            ConfirmAction::DetachHashtag(_, hashtag_name) => {
+                format!("Remove {} from this command?", hashtag_name)
+            }
+        };
         vec![
             Spans::from(vec![
                 Span::raw("  "),
-                Span::styled(
-                    app.error_message.to_owned(),
-                    Style::default().fg(Color::Red),
-                ),
+                Span::styled(confirm_message, Style::default().fg(highlight_color)),
             ]),
             Spans::from(vec![
                 Span::raw("  "),
-                Span::styled("Save", Style::default().fg(Color::Green)),
-                Span::raw(": Enter Key"),
+                Span::styled("Yes", Style::default().fg(key_color)),
+                Span::raw(format!(
+                    ": {} Key",
+                    app.keymap.get_key_name(Action::Confirm)
+                )),
             ]),
             Spans::from(vec![
                 Span::raw("  "),
-                Span::styled("Cancel", Style::default().fg(Color::Green)),
-                Span::raw(": ESC Key"),
+                Span::styled("No", Style::default().fg(key_color)),
+                Span::raw(": Any Other Key"),
             ]),
         ]
-    } else {
-        let select_item: Vec<String> = app.get_select_item();
-        let is_selected_all: bool =
-            app.table_title == SELECT_HASHTAG_TITLE && select_item[0] == ALL_HASHTAG;
+    } else if app.search_mode {
         vec![
             Spans::from(vec![
                 Span::raw("  "),
-                Span::styled(
-                    app.error_message.to_owned(),
-                    Style::default().fg(Color::Red),
-                ),
+                Span::styled("/", Style::default().fg(highlight_color)),
+                Span::raw(app.search_query.to_owned()),
             ]),
             Spans::from(vec![
                 Span::raw("  "),
-                Span::styled("Select", Style::default().fg(Color::Green)),
-                Span::raw(": Arrow Keys and Enter Key"),
+                Span::styled("Select", Style::default().fg(key_color)),
+                Span::raw(format!(
+                    ": {}, {} and {} Keys",
+                    app.keymap.get_search_key_name(Action::MoveUp),
+                    app.keymap.get_search_key_name(Action::MoveDown),
+                    app.keymap.get_search_key_name(Action::Select)
+                )),
             ]),
-            if is_selected_all {
+            Spans::from(vec![
+                Span::raw("  "),
+                Span::styled("Cancel", Style::default().fg(key_color)),
+                Span::raw(": ESC Key"),
+            ]),
+        ]
+    } else if app.edit_mode {
+        vec![
+            if app.error_message.len() == 0 && app.edit_id == MERGE_EDIT_ID {
                 Spans::from(vec![
                     Span::raw("  "),
-                    Span::styled("Quit", Style::default().fg(Color::Green)),
-                    Span::raw(": 'q' Key"),
+                    Span::styled(
+                        format!("Merge {} into", app.get_select_item()[0]),
+                        Style::default().fg(highlight_color),
+                    ),
                 ])
             } else {
                 Spans::from(vec![
                     Span::raw("  "),
-                    Span::styled("Quit", Style::default().fg(Color::Green)),
-                    Span::raw(": 'q' Key, "),
-                    Span::styled("Edit", Style::default().fg(Color::Green)),
-                    Span::raw(": 'e' Key"),
+                    Span::styled(
+                        app.error_message.to_owned(),
+                        Style::default().fg(error_color),
+                    ),
                 ])
             },
+            Spans::from(vec![
+                Span::raw("  "),
+                Span::styled("Save", Style::default().fg(key_color)),
+                Span::raw(": Enter Key"),
+            ]),
+            Spans::from(vec![
+                Span::raw("  "),
+                Span::styled("Cancel", Style::default().fg(key_color)),
+                Span::raw(": ESC Key"),
+            ]),
+        ]
+    } else {
+        let is_selected_pseudo: bool = app.table_title == SELECT_HASHTAG_TITLE
+            && app
+                .state
+                .selected()
+                .map(|i: usize| !app.is_editable_hashtag(&app.hashtags[i][0]))
+                == Some(true);
+        let is_selected_parent: bool = app.view_id == HASHTAG_VIEW_ID
+            && app
+                .state
+                .selected()
+                .map(|i: usize| app.parent_hashtags.contains(&app.hashtags[i][0]))
+                == Some(true);
+        let mut select_spans: Vec<Span> = vec![
+            Span::raw("  "),
+            Span::styled("Select", Style::default().fg(key_color)),
+            Span::raw(format!(
+                ": {}, {} and {} Keys, ",
+                app.keymap.get_key_name(Action::MoveUp),
+                app.keymap.get_key_name(Action::MoveDown),
+                app.keymap.get_key_name(Action::Select)
+            )),
+            Span::styled("Search", Style::default().fg(key_color)),
+            Span::raw(format!(": {} Key", app.keymap.get_key_name(Action::Search))),
+        ];
+        if app.view_id == HASHTAG_VIEW_ID {
+            select_spans.push(Span::raw(", "));
+            select_spans.push(Span::styled("Query", Style::default().fg(key_color)));
+            select_spans.push(Span::raw(format!(
+                ": {} Key",
+                app.keymap.get_key_name(Action::Query)
+            )));
+        } else {
+            select_spans.push(Span::raw(", "));
+            select_spans.push(Span::styled("Sort", Style::default().fg(key_color)));
+            select_spans.push(Span::raw(format!(
+                ": {} Key ({})",
+                app.keymap.get_key_name(Action::Sort),
+                get_sort_name(if app.view_id == QUERY_COMMAND_VIEW_ID {
+                    app.sort_id
+                } else {
+                    app.get_group_sort_id(&app.header_cells[0])
+                })
+            )));
+        }
+        let status_spans: Spans = if app.error_message.len() == 0 && is_command_view {
+            let details: String = match app.get_select_entry() {
+                Some(entry) => format_entry_details(entry),
+                None => String::new(),
+            };
+            Spans::from(vec![
+                Span::raw("  "),
+                Span::styled(details, Style::default().fg(details_color)),
+            ])
+        } else {
+            Spans::from(vec![
+                Span::raw("  "),
+                Span::styled(
+                    app.error_message.to_owned(),
+                    Style::default().fg(error_color),
+                ),
+            ])
+        };
+        vec![
+            status_spans,
+            Spans::from(select_spans),
+            if is_selected_pseudo {
+                let mut spans: Vec<Span> = vec![
+                    Span::raw("  "),
+                    Span::styled("Quit", Style::default().fg(key_color)),
+                    Span::raw(format!(": {} Key", app.keymap.get_key_name(Action::Quit))),
+                ];
+                if is_selected_parent {
+                    spans.push(Span::raw(", "));
+                    spans.push(Span::styled("Fold", Style::default().fg(key_color)));
+                    spans.push(Span::raw(format!(
+                        ": {} and {} Keys",
+                        app.keymap.get_key_name(Action::Back),
+                        app.keymap.get_key_name(Action::Open)
+                    )));
+                }
+                Spans::from(spans)
+            } else if app.view_id == ALL_COMMAND_VIEW_ID || app.view_id == QUERY_COMMAND_VIEW_ID {
+                Spans::from(vec![
+                    Span::raw("  "),
+                    Span::styled("Quit", Style::default().fg(key_color)),
+                    Span::raw(format!(": {} Key, ", app.keymap.get_key_name(Action::Quit))),
+                    Span::styled("Edit", Style::default().fg(key_color)),
+                    Span::raw(format!(": {} Key, ", app.keymap.get_key_name(Action::Edit))),
+                    Span::styled("Tag", Style::default().fg(key_color)),
+                    Span::raw(format!(": {} Key", app.keymap.get_key_name(Action::Tag))),
+                ])
+            } else {
+                let mut spans: Vec<Span> = vec![
+                    Span::raw("  "),
+                    Span::styled("Quit", Style::default().fg(key_color)),
+                    Span::raw(format!(": {} Key, ", app.keymap.get_key_name(Action::Quit))),
+                    Span::styled("Edit", Style::default().fg(key_color)),
+                    Span::raw(format!(": {} Key, ", app.keymap.get_key_name(Action::Edit))),
+                ];
+                if app.view_id == HASHTAG_VIEW_ID {
+                    spans.push(Span::styled("Delete", Style::default().fg(key_color)));
+                    spans.push(Span::raw(format!(
+                        ": {} Key, ",
+                        app.keymap.get_key_name(Action::Delete)
+                    )));
+                    spans.push(Span::styled("Merge", Style::default().fg(key_color)));
+                    spans.push(Span::raw(format!(
+                        ": {} Key",
+                        app.keymap.get_key_name(Action::Merge)
+                    )));
+                    if is_selected_parent {
+                        spans.push(Span::raw(", "));
+                        spans.push(Span::styled("Fold", Style::default().fg(key_color)));
+                        spans.push(Span::raw(format!(
+                            ": {} and {} Keys",
+                            app.keymap.get_key_name(Action::Back),
+                            app.keymap.get_key_name(Action::Open)
+                        )));
+                    }
+                } else {
+                    spans.push(Span::styled("Untag", Style::default().fg(key_color)));
+                    spans.push(Span::raw(format!(
+                        ": {} Key, ",
+                        app.keymap.get_key_name(Action::Delete)
+                    )));
+                    spans.push(Span::styled("Tag", Style::default().fg(key_color)));
+                    spans.push(Span::raw(format!(
+                        ": {} Key",
+                        app.keymap.get_key_name(Action::Tag)
+                    )));
+                }
+                Spans::from(spans)
+            },
         ]
     };
 
     let paragraph: tui::widgets::Paragraph = Paragraph::new(help_text);
     frame.render_widget(paragraph, chunks[1]);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_history::CommandIndex;
+    use std::io::Read;
+
+    #[test]
+    fn here_command_rows_test() {
+        let entry = |command: &str, cwd: &str| -> HistoryEntry {
+            return HistoryEntry {
+                cwd: Some(cwd.to_owned()),
+                ..HistoryEntry::new(command)
+            };
+        };
+        let mut command_index: CommandIndex = CommandIndex::default();
+        command_index.extend(vec![
+            entry("make", "/src/app"),
+            entry("make", "/src/app"),
+            entry("make", "/src/app"),
+            entry("cargo test", "/src/app"),
+            entry("make", "/tmp"),
+        ]);
+        let here_dir: HereDir = HereDir {
+            path: PathBuf::from("/src/app"),
+            is_worktree: false,
+        };
+        let history_map = command_index.to_command_hashmap(Some(&here_dir), &[]);
+        let mut app: App = App::new(history_map, HashtagNormalizer::default(), Config::default());
+        app.sort_id = FREQUENCY_SORT_ID;
+        let get_commands = |app: &App| -> Vec<String> {
+            return app
+                .hashtags
+                .iter()
+                .map(|row: &Vec<String>| row[0].to_owned())
+                .collect();
+        };
+
+        // make was run last in another directory, and most often
+        app.open_hashtag(ALL_HASHTAG);
+        assert_eq!(get_commands(&app), ["make", "cargo test"]);
+        app.open_hashtag(HERE_HASHTAG);
+        assert_eq!(get_commands(&app), ["cargo test", "make"]);
+    }
+
+    #[test]
+    fn write_output_test() {
+        let output_path: PathBuf =
+            std::env::temp_dir().join(format!("history-tidy-output-{}", std::process::id()));
+        let output: Output = Output::File(output_path.clone());
+        let keep_config: RunConfig = RunConfig::default();
+        let strip_config: RunConfig = RunConfig {
+            keep_hashtags: false,
+            ..RunConfig::default()
+        };
+
+        write_output(&output, "kubectl get pods #k8s #debug", &keep_config).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&output_path).unwrap(),
+            "kubectl get pods #k8s #debug\n"
+        );
+        write_output(&output, "kubectl get pods #k8s #debug", &strip_config).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&output_path).unwrap(),
+            "kubectl get pods\n"
+        );
+        write_output(&output, "make #note rm -rf build", &strip_config).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&output_path).unwrap(),
+            "make # rm -rf build\n"
+        );
+
+        std::fs::remove_file(&output_path).unwrap();
+    }
+
+    // `history-tidy --output 3` writes to a descriptor the caller reads, here a pipe. The
+    // descriptor is left open, so more than one line can be written to it.
+    #[test]
+    fn fd_output_test() {
+        let mut pipe_fds: [libc::c_int; 2] = [0; 2];
+        assert_eq!(unsafe { libc::pipe(pipe_fds.as_mut_ptr()) }, 0);
+        let output: Output = Output::Fd(pipe_fds[1]);
+        let run_config: RunConfig = RunConfig {
+            keep_hashtags: false,
+            ..RunConfig::default()
+        };
+
+        write_output(&output, "kubectl get pods #k8s", &run_config).unwrap();
+        write_output(&output, "make #note rm -rf build", &run_config).unwrap();
+        unsafe { libc::close(pipe_fds[1]) };
+        let mut read_output: String = String::new();
+        unsafe { File::from_raw_fd(pipe_fds[0]) }
+            .read_to_string(&mut read_output)
+            .unwrap();
+        assert_eq!(read_output, "kubectl get pods\nmake # rm -rf build\n");
+    }
+}
@@ -1,6 +1,162 @@
 use colored::*;
+use std::fs::OpenOptions;
+use std::io::stdout;
+use std::io::Error;
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::io::RawFd;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
 
 pub fn error_exit(message: &str, err: std::io::Error, exit_code: i32) {
     eprintln!("{}: {} [{}]", "error".red(), message, err);
     std::process::exit(exit_code);
 }
+
+// The UI is drawn on stdout, which is captured in `$(history-tidy --print)`, so it is pointed at
+// the terminal until restore_stdout is called with the returned copy of it.
+pub fn redirect_stdout_to_tty() -> Result<RawFd, Error> {
+    let tty = OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+    unsafe {
+        let stdout_fd: RawFd = libc::dup(libc::STDOUT_FILENO);
+        if stdout_fd < 0 {
+            return Err(Error::last_os_error());
+        }
+        if libc::dup2(tty.as_raw_fd(), libc::STDOUT_FILENO) < 0 {
+            let e: Error = Error::last_os_error();
+            libc::close(stdout_fd);
+            return Err(e);
+        }
+        return Ok(stdout_fd);
+    }
+}
+
+pub fn restore_stdout(stdout_fd: RawFd) -> Result<(), Error> {
+    stdout().flush()?;
+    unsafe {
+        let result: i32 = libc::dup2(stdout_fd, libc::STDOUT_FILENO);
+        libc::close(stdout_fd);
+        if result < 0 {
+            return Err(Error::last_os_error());
+        }
+    }
+    return Ok(());
+}
+
+// "*" matches any text, "?" any one character, and the pattern has to match the whole text.
+pub fn matches_glob(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let mut pattern_index: usize = 0;
+    let mut text_index: usize = 0;
+    // where the last "*" was, and the text it matches so far ends
+    let mut star: Option<(usize, usize)> = None;
+    while text_index < text.len() {
+        if pattern_index < pattern.len()
+            && (pattern[pattern_index] == '?' || pattern[pattern_index] == text[text_index])
+        {
+            pattern_index += 1;
+            text_index += 1;
+        } else if pattern_index < pattern.len() && pattern[pattern_index] == '*' {
+            star = Some((pattern_index, text_index));
+            pattern_index += 1;
+        } else if let Some((star_index, star_end)) = star {
+            // lets the "*" take one more character
+            pattern_index = star_index + 1;
+            text_index = star_end + 1;
+            star = Some((star_index, star_end + 1));
+        } else {
+            return false;
+        }
+    }
+    return pattern[pattern_index..].iter().all(|c: &char| *c == '*');
+}
+
+pub fn get_unix_time() -> i64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs() as i64,
+        Err(_) => 0,
+    }
+}
+
+pub fn format_relative_time(timestamp: i64, now: i64) -> String {
+    let elapsed: i64 = now - timestamp;
+    if elapsed < 60 {
+        return "just now".to_owned();
+    } else if elapsed < 60 * 60 {
+        return format!("{}m ago", elapsed / 60);
+    } else if elapsed < 60 * 60 * 24 {
+        return format!("{}h ago", elapsed / (60 * 60));
+    } else if elapsed < 60 * 60 * 24 * 30 {
+        return format!("{}d ago", elapsed / (60 * 60 * 24));
+    } else if elapsed < 60 * 60 * 24 * 365 {
+        return format!("{}mo ago", elapsed / (60 * 60 * 24 * 30));
+    }
+    return format!("{}y ago", elapsed / (60 * 60 * 24 * 365));
+}
+
+// Runs weighted by how recently the command was last run, the same buckets as zoxide uses.
+pub fn get_frecency(run_count: usize, last_used: Option<i64>, now: i64) -> i64 {
+    let elapsed: i64 = match last_used {
+        Some(timestamp) => now - timestamp,
+        None => i64::MAX,
+    };
+    let weight: i64 = if elapsed < 60 * 60 {
+        16
+    } else if elapsed < 60 * 60 * 24 {
+        8
+    } else if elapsed < 60 * 60 * 24 * 7 {
+        2
+    } else {
+        1
+    };
+    return run_count as i64 * weight;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_relative_time_test() {
+        let now: i64 = 1690000000;
+        assert_eq!(format_relative_time(now - 5, now), "just now");
+        assert_eq!(format_relative_time(now - 60 * 5, now), "5m ago");
+        assert_eq!(format_relative_time(now - 60 * 60 * 2, now), "2h ago");
+        assert_eq!(format_relative_time(now - 60 * 60 * 24 * 3, now), "3d ago");
+        assert_eq!(
+            format_relative_time(now - 60 * 60 * 24 * 65, now),
+            "2mo ago"
+        );
+        assert_eq!(
+            format_relative_time(now - 60 * 60 * 24 * 800, now),
+            "2y ago"
+        );
+    }
+
+    #[test]
+    fn matches_glob_test() {
+        assert!(matches_glob("ls", "ls"));
+        assert!(!matches_glob("ls", "ls -a"));
+        assert!(matches_glob("cd *", "cd ~/src"));
+        assert!(!matches_glob("cd *", "cd"));
+        assert!(matches_glob("*secret*", "export TOKEN=secret #env"));
+        assert!(matches_glob("git ?ull*", "git pull --rebase"));
+        assert!(matches_glob("*", ""));
+        assert!(matches_glob("a*b*c", "aXbYbZc"));
+        assert!(!matches_glob("a*b*c", "aXbYbZ"));
+    }
+
+    #[test]
+    fn get_frecency_test() {
+        let now: i64 = 1690000000;
+        assert_eq!(get_frecency(3, Some(now - 60), now), 48);
+        assert_eq!(get_frecency(3, Some(now - 60 * 60 * 24 * 30), now), 3);
+        assert_eq!(get_frecency(3, None, now), 3);
+        // a command run once a minute ago beats one run five times last month
+        assert!(
+            get_frecency(1, Some(now - 60), now)
+                > get_frecency(5, Some(now - 60 * 60 * 24 * 30), now)
+        );
+    }
+}